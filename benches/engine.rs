@@ -0,0 +1,209 @@
+//! Benchmarks of the processing engine on generated fixtures.
+//!
+//! Run with `cargo bench`, save a baseline with `cargo bench -- --save-baseline <name>`
+//! and compare a later run with `cargo bench -- --baseline <name>`.
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use jones_project::{
+    currency::Currency,
+    db::Db,
+    generator::{Config, Format, Generator},
+    transaction::{Transaction, TransactionKind, TransactionState},
+};
+
+/// Number of rows of every fixture
+const SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+/// Few clients with long histories and many clients with short ones
+const CLIENTS: [u16; 2] = [10, 1_000];
+/// Number of rows processed by every iteration of the per kind benchmarks
+const BATCH: u32 = 10_000;
+
+struct Fixture {
+    name: String,
+    rows: usize,
+    csv: String,
+}
+
+fn fixtures() -> Vec<Fixture> {
+    let mut fixtures = vec![];
+    for clients in CLIENTS {
+        for rows in SIZES {
+            let mut csv = String::new();
+            csv.push_str(Format::Csv.header().unwrap());
+            csv.push('\n');
+            for row in Generator::new(Config::new(clients, rows)) {
+                row.write(Format::Csv, &mut csv);
+            }
+            fixtures.push(Fixture {
+                name: format!("{}x{}", rows, clients),
+                rows,
+                csv,
+            });
+        }
+    }
+    fixtures
+}
+
+fn parse(csv: &str) -> Vec<Transaction> {
+    csv::Reader::from_reader(csv.as_bytes())
+        .deserialize()
+        .filter_map(Result::ok)
+        .collect()
+}
+
+fn process(db: &Db, txs: Vec<Transaction>) {
+    for tx in txs {
+        let _ = db.process(tx);
+    }
+}
+
+fn tx(kind: TransactionKind, tx: u32, amount: Option<f32>) -> Transaction {
+    Transaction {
+        kind,
+        client_id: (tx % 100) as u16 + 1,
+        tx,
+        amount,
+        currency: Currency::NONE,
+        state: TransactionState::Processing,
+    }
+}
+
+/// Rows of `kind` with ids `1..=BATCH` spread over `clients` clients
+fn batch(kind: TransactionKind, clients: u32) -> Vec<Transaction> {
+    let amount = matches!(kind, TransactionKind::Deposit | TransactionKind::Withdrawal);
+    (1..=BATCH)
+        .map(|id| Transaction {
+            client_id: (id % clients) as u16 + 1,
+            ..tx(kind, id, amount.then_some(1.0))
+        })
+        .collect()
+}
+
+fn bench_parse(c: &mut Criterion, fixtures: &[Fixture]) {
+    let mut group = c.benchmark_group("parse");
+    for fixture in fixtures {
+        group.throughput(Throughput::Elements(fixture.rows as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(&fixture.name),
+            &fixture.csv,
+            |b, csv| b.iter(|| parse(csv)),
+        );
+    }
+    group.finish();
+}
+
+/// Every kind is measured on a database prepared with the rows it depends on.
+/// A chargeback locks the account, so chargebacks have a client per tx id to measure applied rows only
+fn bench_process(c: &mut Criterion) {
+    use TransactionKind::*;
+    let mut group = c.benchmark_group("process");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    let setups: [(TransactionKind, &[TransactionKind], u32); 5] = [
+        (Deposit, &[], 100),
+        (Withdrawal, &[], 100),
+        (Dispute, &[Deposit], 100),
+        (Resolve, &[Deposit, Dispute], 100),
+        (Chargeback, &[Deposit, Dispute], BATCH),
+    ];
+    for (kind, before, clients) in setups {
+        let db = Db::default();
+        group.bench_function(kind.to_string(), |b| {
+            b.iter_batched(
+                || {
+                    db.clean();
+                    // withdrawals need funds under other ids
+                    if kind == Withdrawal {
+                        for client_id in 1..=100 {
+                            let mut funds = tx(Deposit, BATCH + client_id, Some(1e6));
+                            funds.client_id = client_id as u16;
+                            let _ = db.process(funds);
+                        }
+                    }
+                    for kind in before {
+                        process(&db, batch(*kind, clients));
+                    }
+                    batch(kind, clients)
+                },
+                |txs| process(&db, txs),
+                BatchSize::LargeInput,
+            )
+        });
+        db.clean();
+    }
+    group.finish();
+}
+
+fn bench_end_to_end(c: &mut Criterion, fixtures: &[Fixture]) {
+    let dir = std::env::temp_dir().join(format!("jones-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut group = c.benchmark_group("end_to_end");
+    for fixture in fixtures {
+        let path: PathBuf = dir.join(format!("{}.csv", fixture.name));
+        std::fs::write(&path, &fixture.csv).unwrap();
+        group.throughput(Throughput::Elements(fixture.rows as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(&fixture.name),
+            &path,
+            |b, path| {
+                let db = Db::default();
+                b.iter(|| {
+                    db.clean();
+                    let mut rdr = csv::Reader::from_path(path).unwrap();
+                    for record in rdr.deserialize::<Transaction>().filter_map(Result::ok) {
+                        if record.is_valid() {
+                            let _ = db.process(record);
+                        }
+                    }
+                    let mut out = String::from("client,available,held,total,locked\n");
+                    for account in db.accounts().values() {
+                        out.push_str(&account.to_csv_row());
+                        out.push('\n');
+                    }
+                    out
+                });
+                db.clean();
+            },
+        );
+    }
+    group.finish();
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+fn bench_serialize(c: &mut Criterion, fixtures: &[Fixture]) {
+    let mut group = c.benchmark_group("serialize");
+    for fixture in fixtures.iter().filter(|f| f.rows == SIZES[SIZES.len() - 1]) {
+        let db = Db::default();
+        db.clean();
+        process(&db, parse(&fixture.csv));
+        let accounts = db.accounts();
+        db.clean();
+        group.throughput(Throughput::Elements(accounts.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(&fixture.name),
+            &accounts,
+            |b, accounts| {
+                b.iter(|| {
+                    let mut out = String::from("client,available,held,total,locked\n");
+                    for account in accounts.values() {
+                        out.push_str(&account.to_csv_row());
+                        out.push('\n');
+                    }
+                    out
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn benches(c: &mut Criterion) {
+    let fixtures = fixtures();
+    bench_parse(c, &fixtures);
+    bench_process(c);
+    bench_end_to_end(c, &fixtures);
+    bench_serialize(c, &fixtures);
+}
+
+criterion_group!(engine, benches);
+criterion_main!(engine);
@@ -0,0 +1,21 @@
+//! Arbitrary bytes through the CSV reader, `Transaction` deserialization and validation
+#![no_main]
+
+use jones_project::transaction::Transaction;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+    let Ok(headers) = rdr.headers().cloned() else {
+        return;
+    };
+    let mut row = csv::StringRecord::new();
+    while let Ok(true) = rdr.read_record(&mut row) {
+        if let Ok(tx) = row.deserialize::<Transaction>(Some(&headers)) {
+            if tx.is_valid() {
+                assert!(tx.amount.is_none_or(|x| x.is_finite() && x > 0.0), "{}", tx);
+            }
+            let _ = tx.to_string();
+        }
+    }
+});
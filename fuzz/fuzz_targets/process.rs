@@ -0,0 +1,73 @@
+//! Structured sequences of transactions through `Db::process`, with and without a dispute window
+#![no_main]
+
+use arbitrary::Arbitrary;
+use jones_project::{
+    currency::Currency,
+    db::{Db, MissingPolicy},
+    retention::{DisputeWindow, Retention},
+    transaction::{Transaction, TransactionKind, TransactionState},
+};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Row {
+    kind: Kind,
+    client_id: u16,
+    /// Small range of ids, so rows often refer to each other
+    tx: u8,
+    amount: Option<f32>,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    ignore_missing: bool,
+    window: Option<u8>,
+    rows: Vec<Row>,
+}
+
+fuzz_target!(|input: Input| {
+    let retention = Retention {
+        window: input.window.map(|n| DisputeWindow::Count(n as usize)),
+        spill: None,
+    };
+    let db = Db::default()
+        .with_missing_policy(if input.ignore_missing {
+            MissingPolicy::Ignore
+        } else {
+            MissingPolicy::Reject
+        })
+        .with_retention(retention);
+    db.clean();
+    for row in input.rows {
+        let kind = match row.kind {
+            Kind::Deposit => TransactionKind::Deposit,
+            Kind::Withdrawal => TransactionKind::Withdrawal,
+            Kind::Dispute => TransactionKind::Dispute,
+            Kind::Resolve => TransactionKind::Resolve,
+            Kind::Chargeback => TransactionKind::Chargeback,
+        };
+        let tx = Transaction {
+            kind,
+            client_id: row.client_id % 8,
+            tx: row.tx as u32,
+            amount: row.amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        };
+        // invalid rows are filtered by the reader, but they must not panic the engine either
+        let _ = db.process(tx);
+    }
+    for account in db.accounts().values() {
+        let _ = account.to_csv_row();
+    }
+});
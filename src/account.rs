@@ -1,102 +1,130 @@
+use std::collections::BTreeMap;
 use std::fmt::Display;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::{currency::Currency, error::Error};
 
-#[derive(Debug, Serialize)]
+/// Balances of a client in one currency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Funds {
+    pub available: f32,
+    pub held: f32,
+    pub total: f32,
+}
+
+/// Balances of a client in every currency it has rows in.
+/// Locks and holds apply to the client, so to all of its currencies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Account {
     client_id: u16,
-    available: f32,
-    held: f32,
-    total: f32,
+    funds: BTreeMap<Currency, Funds>,
     locked: bool,
+    /// Withdrawals are rejected while the account is on hold
+    on_hold: bool,
+    /// Why the account was last locked or put on hold
+    reason: Option<String>,
 }
 impl Account {
     pub fn new(client_id: u16) -> Self {
         Account {
             client_id,
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
+            funds: BTreeMap::new(),
             locked: false,
+            on_hold: false,
+            reason: None,
         }
     }
     // @TODO since amount is f32 that is can be negative
     // I need to make sure that for deposit it is always positive
     // also think maybe that could be one method for withdraw as well with negative amount
-    pub fn deposit(&mut self, amount: f32) -> Result<(), Error> {
+    pub fn deposit(&mut self, currency: Currency, amount: f32) -> Result<(), Error> {
         self.check_locked()?;
         log::debug!("Deposit to cleint #{}, amount: {}", self.client_id, amount);
-        self.available += amount;
-        self.clalc_total();
+        let funds = self.funds.entry(currency).or_default();
+        funds.available += amount;
+        funds.calc_total();
         log::debug!("Account state {}", self);
         Ok(())
     }
-    pub fn withdraw(&mut self, amount: f32) -> Result<(), Error> {
+    pub fn withdraw(&mut self, currency: Currency, amount: f32) -> Result<(), Error> {
         self.check_locked()?;
+        if self.on_hold {
+            return Err(AccountError::OnHold.into());
+        }
         log::debug!(
             "Withdraw from cleint #{}, amount: {}",
             self.client_id,
             amount
         );
-        let ret = if self.available >= amount {
-            self.available -= amount;
+        let ret = if self.funds(currency).available >= amount {
+            let funds = self.funds.entry(currency).or_default();
+            funds.available -= amount;
+            funds.calc_total();
             Ok(())
         } else {
             Err(AccountError::Withdraw.into())
         };
-        self.clalc_total();
         log::debug!("Account state {}", self);
         ret
     }
     /// `is_deposit` notify if current transaction is Deposite or Withdraw
-    pub fn dispute(&mut self, amount: f32, is_deposit: bool) -> Result<(), Error> {
+    pub fn dispute(
+        &mut self,
+        currency: Currency,
+        amount: f32,
+        is_deposit: bool,
+    ) -> Result<(), Error> {
         self.check_locked()?;
         log::debug!("Dispute cleint #{} with amount: {}", self.client_id, amount);
+        let funds = self.funds.entry(currency).or_default();
         let ret = if is_deposit {
-            if self.available >= amount {
-                self.available -= amount;
-                self.held += amount;
+            if funds.available >= amount {
+                funds.available -= amount;
+                funds.held += amount;
                 Ok(())
             } else {
                 Err(AccountError::Dispute.into())
             }
         } else {
-            self.held += amount;
+            funds.held += amount;
             Ok(())
         };
-        self.clalc_total();
+        funds.calc_total();
         log::debug!("Account state {}", self);
         ret
     }
 
     /// `is_deposit` notify if current transaction is Deposite or Withdraw
-    pub fn resolve(&mut self, amount: f32) -> Result<(), Error> {
+    pub fn resolve(&mut self, currency: Currency, amount: f32) -> Result<(), Error> {
         self.check_locked()?;
-        let ret = if self.held >= amount {
-            self.held -= amount;
-            self.available += amount;
-            Ok(())
-        } else {
-            Err(AccountError::Resolve.into())
+        let ret = match self.funds.get_mut(&currency) {
+            Some(funds) if funds.held >= amount => {
+                funds.held -= amount;
+                funds.available += amount;
+                funds.calc_total();
+                Ok(())
+            }
+            _ => Err(AccountError::Resolve.into()),
         };
-        self.clalc_total();
         log::debug!("Account state {}", self);
         ret
     }
 
-    /// `is_deposit` notify if current transaction is Deposite or Withdraw
-    pub fn chargeback(&mut self, amount: f32) -> Result<(), Error> {
+    /// Takes the disputed `amount` out of the held funds and locks the account.
+    /// The dispute already moved it from available to held funds, so charging available funds
+    /// would take it from the client twice and leave it held forever
+    pub fn chargeback(&mut self, currency: Currency, amount: f32) -> Result<(), Error> {
         self.check_locked()?;
-        let ret = if self.available >= amount {
-            self.available -= amount;
-            self.lock();
-            Ok(())
-        } else {
-            Err(AccountError::Chargeback.into())
+        let ret = match self.funds.get_mut(&currency) {
+            Some(funds) if funds.held >= amount => {
+                funds.held -= amount;
+                funds.calc_total();
+                self.lock("chargeback");
+                Ok(())
+            }
+            _ => Err(AccountError::Chargeback.into()),
         };
-        self.clalc_total();
         log::debug!("Account state {}", self);
         ret
     }
@@ -112,31 +140,102 @@ impl Account {
             Ok(())
         }
     }
-    fn clalc_total(&mut self) {
-        self.total = self.available + self.held;
+    pub fn is_on_hold(&self) -> bool {
+        self.on_hold
+    }
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+    /// Rejects further withdrawals of the account
+    pub fn hold(&mut self, reason: &str) {
+        self.on_hold = true;
+        self.reason = Some(reason.to_string());
+    }
+    pub fn client_id(&self) -> u16 {
+        self.client_id
+    }
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+    /// Balances in the currency, zero if the client has no rows in it
+    pub fn funds(&self, currency: Currency) -> Funds {
+        self.funds.get(&currency).copied().unwrap_or_default()
+    }
+    /// Replaces the balances in the currency, e.g. to replay recorded changes
+    pub fn set_funds(&mut self, currency: Currency, funds: Funds) {
+        self.funds.insert(currency, funds);
+    }
+    /// Currencies the client has rows in with their balances, ordered by currency
+    pub fn currencies(&self) -> impl Iterator<Item = (Currency, Funds)> + '_ {
+        self.funds
+            .iter()
+            .map(|(currency, funds)| (*currency, *funds))
+    }
+    /// Returns `true` if the client has rows in a currency other than the default one
+    pub fn has_currencies(&self) -> bool {
+        self.funds.keys().any(|c| !c.is_default())
+    }
+    /// Available funds in the default currency
+    pub fn available(&self) -> f32 {
+        self.funds(Currency::default()).available
     }
+    /// Held funds in the default currency
+    pub fn held(&self) -> f32 {
+        self.funds(Currency::default()).held
+    }
+    /// Total funds in the default currency
+    pub fn total(&self) -> f32 {
+        self.funds(Currency::default()).total
+    }
+    /// `client,available,held,total,locked` of the default currency
     pub fn to_csv_row(&self) -> String {
         format!(
             "{},{},{},{},{}",
-            self.client_id, self.available, self.held, self.total, self.locked
+            self.client_id,
+            self.available(),
+            self.held(),
+            self.total(),
+            self.locked
         )
     }
-    fn lock(&mut self) {
+    /// `client,currency,available,held,total,locked` of every currency, or of the default one if there are no rows
+    pub fn to_currency_rows(&self) -> Vec<String> {
+        let row = |currency: Currency, funds: Funds| {
+            format!(
+                "{},{},{},{},{},{}",
+                self.client_id, currency, funds.available, funds.held, funds.total, self.locked
+            )
+        };
+        if self.funds.is_empty() {
+            return vec![row(Currency::default(), Funds::default())];
+        }
+        self.currencies()
+            .map(|(currency, funds)| row(currency, funds))
+            .collect()
+    }
+    pub fn lock(&mut self, reason: &str) {
         self.locked = true;
+        self.reason = Some(reason.to_string());
+    }
+}
+impl Funds {
+    fn calc_total(&mut self) {
+        self.total = self.available + self.held;
     }
 }
 impl Display for Account {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{{ client_id: {}, available: {}, held: {}, total: {}, locked: {:?} }}",
-            self.client_id, self.available, self.held, self.total, self.locked
+            "{{ client_id: {}, funds: {:?}, locked: {:?} }}",
+            self.client_id, self.funds, self.locked
         )
     }
 }
 #[derive(Debug, PartialEq)]
 pub enum AccountError {
     Locked,
+    OnHold,
     Withdraw,
     Dispute,
     Resolve,
@@ -146,6 +245,7 @@ impl Display for AccountError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Locked => write!(f, "Account is locked"),
+            Self::OnHold => write!(f, "Account is on hold and cannot withdraw"),
             Self::Withdraw => write!(f, "Account has not enough money available to withdraw"),
             Self::Dispute => write!(f, "Account has not enough money available to dispute"),
             Self::Resolve => write!(f, "Account has not enough money available to resolve"),
@@ -153,6 +253,20 @@ impl Display for AccountError {
         }
     }
 }
+impl std::error::Error for AccountError {}
+impl AccountError {
+    /// Stable code of the error used in reports and summaries
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Locked => "account_locked",
+            Self::OnHold => "account_on_hold",
+            Self::Withdraw => "account_withdraw",
+            Self::Dispute => "account_dispute",
+            Self::Resolve => "account_resolve",
+            Self::Chargeback => "account_chargeback",
+        }
+    }
+}
 impl From<AccountError> for Error {
     fn from(value: AccountError) -> Self {
         Self::Account(value)
@@ -163,17 +277,20 @@ mod tests {
     use crate::account::AccountError;
 
     use super::Account;
+    use crate::currency::Currency;
+
+    const NONE: Currency = Currency::NONE;
 
     #[test]
     fn test_deposit() {
         let amount = 1.0;
         let mut acc = Account::new(1);
 
-        let result = acc.deposit(amount);
+        let result = acc.deposit(NONE, amount);
         assert_eq!(Ok(()), result);
-        assert_eq!(amount, acc.total);
-        assert_eq!(amount, acc.available);
-        assert_eq!(0.0, acc.held);
+        assert_eq!(amount, acc.total());
+        assert_eq!(amount, acc.available());
+        assert_eq!(0.0, acc.held());
     }
 
     #[test]
@@ -181,13 +298,13 @@ mod tests {
         let amount = 2.0;
         let total = 4.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
+        let _ = acc.deposit(NONE, total);
 
-        let result = acc.withdraw(amount);
+        let result = acc.withdraw(NONE, amount);
         assert_eq!(Ok(()), result);
-        assert_eq!(total - amount, acc.total);
-        assert_eq!(total - amount, acc.available);
-        assert_eq!(0.0, acc.held);
+        assert_eq!(total - amount, acc.total());
+        assert_eq!(total - amount, acc.available());
+        assert_eq!(0.0, acc.held());
         assert!(!acc.locked);
     }
     #[test]
@@ -195,14 +312,14 @@ mod tests {
         let amount = 5.0;
         let total = 4.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
+        let _ = acc.deposit(NONE, total);
 
-        let result = acc.withdraw(amount);
+        let result = acc.withdraw(NONE, amount);
         assert_eq!(Err(AccountError::Withdraw.into()), result);
 
-        assert_eq!(total, acc.total);
-        assert_eq!(total, acc.available);
-        assert_eq!(0.0, acc.held);
+        assert_eq!(total, acc.total());
+        assert_eq!(total, acc.available());
+        assert_eq!(0.0, acc.held());
         assert!(!acc.locked);
     }
     #[test]
@@ -210,12 +327,12 @@ mod tests {
         let amount = 2.0;
         let total = 4.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
-        let result = acc.dispute(amount, true);
+        let _ = acc.deposit(NONE, total);
+        let result = acc.dispute(NONE, amount, true);
         assert_eq!(Ok(()), result);
-        assert_eq!(total, acc.total);
-        assert_eq!(total - amount, acc.available);
-        assert_eq!(amount, acc.held);
+        assert_eq!(total, acc.total());
+        assert_eq!(total - amount, acc.available());
+        assert_eq!(amount, acc.held());
         assert!(!acc.locked);
     }
     #[test]
@@ -223,12 +340,12 @@ mod tests {
         let amount = 2.0;
         let total = 1.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
-        let result = acc.dispute(amount, false);
+        let _ = acc.deposit(NONE, total);
+        let result = acc.dispute(NONE, amount, false);
         assert_eq!(Ok(()), result);
-        assert_eq!(total + amount, acc.total);
-        assert_eq!(total, acc.available);
-        assert_eq!(amount, acc.held);
+        assert_eq!(total + amount, acc.total());
+        assert_eq!(total, acc.available());
+        assert_eq!(amount, acc.held());
         assert!(!acc.locked);
     }
     #[test]
@@ -236,13 +353,13 @@ mod tests {
         let amount = 5.0;
         let total = 4.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
+        let _ = acc.deposit(NONE, total);
 
-        let result = acc.dispute(amount, true);
+        let result = acc.dispute(NONE, amount, true);
         assert_eq!(Err(AccountError::Dispute.into()), result);
-        assert_eq!(total, acc.total);
-        assert_eq!(total, acc.available);
-        assert_eq!(0.0, acc.held);
+        assert_eq!(total, acc.total());
+        assert_eq!(total, acc.available());
+        assert_eq!(0.0, acc.held());
         assert!(!acc.locked);
     }
     #[test]
@@ -250,14 +367,14 @@ mod tests {
         let deposit = 4.0;
         let withdraw = 2.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(deposit);
-        let _ = acc.withdraw(withdraw);
-        let _ = acc.dispute(withdraw, false);
-        let result = acc.resolve(withdraw);
+        let _ = acc.deposit(NONE, deposit);
+        let _ = acc.withdraw(NONE, withdraw);
+        let _ = acc.dispute(NONE, withdraw, false);
+        let result = acc.resolve(NONE, withdraw);
         assert_eq!(Ok(()), result);
-        assert_eq!(deposit, acc.total);
-        assert_eq!(deposit, acc.available);
-        assert_eq!(0.0, acc.held);
+        assert_eq!(deposit, acc.total());
+        assert_eq!(deposit, acc.available());
+        assert_eq!(0.0, acc.held());
         assert!(!acc.locked);
     }
     #[test]
@@ -266,13 +383,13 @@ mod tests {
         let dispute = 3.0;
         let total = 4.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
-        let _ = acc.dispute(dispute, true);
-        let result = acc.resolve(resolve);
+        let _ = acc.deposit(NONE, total);
+        let _ = acc.dispute(NONE, dispute, true);
+        let result = acc.resolve(NONE, resolve);
         assert_eq!(Err(AccountError::Resolve.into()), result);
-        assert_eq!(total, acc.total);
-        assert_eq!(total - dispute, acc.available);
-        assert_eq!(dispute, acc.held);
+        assert_eq!(total, acc.total());
+        assert_eq!(total - dispute, acc.available());
+        assert_eq!(dispute, acc.held());
         assert!(!acc.locked);
     }
     #[test]
@@ -280,14 +397,18 @@ mod tests {
         let amount = 2.0;
         let total = 4.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
-        let _ = acc.dispute(amount, true);
-        let _ = acc.resolve(amount);
-        let result = acc.chargeback(amount);
+        let _ = acc.deposit(NONE, total);
+        let _ = acc.dispute(NONE, amount, true);
+        // charged back while disputed, a resolved dispute has nothing held to charge back
+        let result = acc.chargeback(NONE, amount);
         assert_eq!(Ok(()), result);
-        assert_eq!(total - amount, acc.total);
-        assert_eq!(total - amount, acc.available);
-        assert_eq!(0.0, acc.held);
+        assert_eq!(total - amount, acc.total());
+        assert_eq!(total - amount, acc.available());
+        assert_eq!(0.0, acc.held());
+        assert_eq!(
+            Err(AccountError::Locked.into()),
+            acc.chargeback(NONE, amount)
+        );
         assert!(acc.locked);
     }
     #[test]
@@ -296,13 +417,34 @@ mod tests {
         let dispute = 3.0;
         let total = 4.0;
         let mut acc = Account::new(1);
-        let _ = acc.deposit(total);
-        let _ = acc.dispute(dispute, true);
-        let result = acc.chargeback(resolve);
+        let _ = acc.deposit(NONE, total);
+        let _ = acc.dispute(NONE, dispute, true);
+        let result = acc.chargeback(NONE, resolve);
         assert_eq!(Err(AccountError::Chargeback.into()), result);
-        assert_eq!(total, acc.total);
-        assert_eq!(total - dispute, acc.available);
-        assert_eq!(dispute, acc.held);
+        assert_eq!(total, acc.total());
+        assert_eq!(total - dispute, acc.available());
+        assert_eq!(dispute, acc.held());
         assert!(!acc.locked);
     }
+    #[test]
+    fn test_currencies() {
+        let usd: Currency = "USD".parse().unwrap();
+        let mut acc = Account::new(1);
+        let _ = acc.deposit(NONE, 1.0);
+        let _ = acc.deposit(usd, 3.0);
+        assert!(acc.has_currencies());
+
+        assert_eq!(Err(AccountError::Withdraw.into()), acc.withdraw(NONE, 2.0));
+        assert_eq!(Ok(()), acc.dispute(usd, 2.0, true));
+        assert_eq!(1.0, acc.available());
+        assert_eq!((1.0, 2.0, 3.0), {
+            let funds = acc.funds(usd);
+            (funds.available, funds.held, funds.total)
+        });
+        assert_eq!(Ok(()), acc.chargeback(usd, 2.0));
+        assert_eq!(
+            vec!["1,,1,0,1,true", "1,USD,1,0,1,true"],
+            acc.to_currency_rows()
+        );
+    }
 }
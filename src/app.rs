@@ -1,12 +1,173 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use clap::Parser;
+
+use crate::currency::Currency;
+use crate::db::MissingPolicy;
+use crate::engine::{InputFormat, Until};
+use crate::retention::{DisputeWindow, Retention};
+use crate::rules::CurrencyList;
+use crate::screening::ScreeningMode;
+use crate::statement::StatementFormat;
+use crate::threshold::Thresholds;
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct CliApp {
+    /// Input file, `-` reads the standard input
     pub filename: Option<PathBuf>,
+    /// Clients written to `--statement`, all clients are written if none are given
     #[arg(short, long)]
     pub accont_filter: Vec<u16>,
     #[arg(short, long)]
     pub logger: bool,
+    /// Skip dispute/resolve/chargeback rows referring to an unknown transaction instead of rejecting them
+    #[arg(long)]
+    pub ignore_missing: bool,
+    /// Keep at most this number of transactions available for disputes
+    #[arg(long, conflicts_with = "dispute_window_age")]
+    pub dispute_window_count: Option<usize>,
+    /// Keep transactions available for disputes for this number of rows
+    #[arg(long)]
+    pub dispute_window_age: Option<u64>,
+    /// File to spill transactions out of the dispute window to, instead of dropping them
+    #[arg(long)]
+    pub spill: Option<PathBuf>,
+    /// Write the run summary as JSON to this file instead of printing it to stderr
+    #[arg(long)]
+    pub stats: Option<PathBuf>,
+    /// Write `line,client,tx,code` of every rejected row to this file
+    #[arg(long)]
+    pub rejects: Option<PathBuf>,
+    /// JSON file with validation rules added to the default ones, e.g. `{"rules": [{"rule": "max_decimals", "places": 4}]}`
+    #[arg(long)]
+    pub rules: Option<PathBuf>,
+    /// JSON file with fraud rules evaluated on applied rows, e.g. `{"rules": [{"rule": "withdrawals", "count": 3, "window": 5, "action": "hold"}]}`
+    #[arg(long)]
+    pub fraud: Option<PathBuf>,
+    /// File with client ids, one per line, whose rows are rejected with `client_blocked`.
+    /// The file is reloaded when it changes while the input is read
+    #[arg(long, conflicts_with = "allowlist")]
+    pub blocklist: Option<PathBuf>,
+    /// File with the only client ids, one per line, whose rows are processed
+    #[arg(long)]
+    pub allowlist: Option<PathBuf>,
+    /// Lock existing accounts of blocked clients
+    #[arg(long)]
+    pub lock_blocked: bool,
+    /// Report deposits and withdrawals above this amount
+    #[arg(long, requires = "large_report")]
+    pub large_amount: Option<f32>,
+    /// Report clients whose deposits and withdrawals in one currency within `--aggregate-window` rows sum above this amount
+    #[arg(long, requires = "large_report")]
+    pub aggregate_amount: Option<f32>,
+    /// Number of rows the aggregate of `--aggregate-amount` is counted over
+    #[arg(long, default_value_t = 100)]
+    pub aggregate_window: u64,
+    /// Write `line,rule,client,txs,amounts,aggregate` of transactions crossing a threshold to this file
+    #[arg(long)]
+    pub large_report: Option<PathBuf>,
+    /// Write every applied and rejected row with the running balances after it to this file
+    #[arg(long)]
+    pub statement: Option<PathBuf>,
+    /// Format of the statement file
+    #[arg(long, value_enum, default_value_t = StatementFormat::Csv)]
+    pub statement_format: StatementFormat,
+    /// Stop after the first row with this transaction id and print the accounts as of it
+    #[arg(long, conflicts_with = "until_line")]
+    pub until_tx: Option<u32>,
+    /// Stop after this input line and print the accounts as of it
+    #[arg(long)]
+    pub until_line: Option<u64>,
+    /// Continue from the accounts and transactions of a state file written by `--save-state`
+    #[arg(long)]
+    pub load_state: Option<PathBuf>,
+    /// Write the accounts and transactions to this state file after the input is processed
+    #[arg(long)]
+    pub save_state: Option<PathBuf>,
+    /// Allowed currencies with their decimal places, e.g. `USD:2,BTC:8`.
+    /// Rows with another currency or more decimal places are rejected with `rule_currency`
+    #[arg(long, value_delimiter = ',', value_parser = parse_currency)]
+    pub currencies: Vec<(Currency, u32)>,
+    /// Format of the input file
+    #[arg(long, value_enum, default_value_t = InputFormat::Csv)]
+    pub format: InputFormat,
+}
+impl CliApp {
+    pub fn missing_policy(&self) -> MissingPolicy {
+        if self.ignore_missing {
+            MissingPolicy::Ignore
+        } else {
+            MissingPolicy::Reject
+        }
+    }
+    pub fn until(&self) -> Option<Until> {
+        match (self.until_tx, self.until_line) {
+            (Some(tx), _) => Some(Until::Tx(tx)),
+            (_, Some(line)) => Some(Until::Line(line)),
+            _ => None,
+        }
+    }
+    pub fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            amount: self.large_amount,
+            aggregate: self.aggregate_amount,
+            window: self.aggregate_window,
+        }
+    }
+    pub fn currency_list(&self) -> Option<CurrencyList> {
+        (!self.currencies.is_empty()).then(|| CurrencyList {
+            currencies: self.currencies.iter().copied().collect(),
+        })
+    }
+    /// Screening list file and its mode
+    pub fn screening(&self) -> Option<(ScreeningMode, &Path)> {
+        match (&self.blocklist, &self.allowlist) {
+            (Some(path), _) => Some((ScreeningMode::Block, path)),
+            (_, Some(path)) => Some((ScreeningMode::Allow, path)),
+            _ => None,
+        }
+    }
+    pub fn retention(&self) -> Retention {
+        let window = match (self.dispute_window_count, self.dispute_window_age) {
+            (Some(n), _) => Some(DisputeWindow::Count(n)),
+            (_, Some(age)) => Some(DisputeWindow::Age(age)),
+            _ => None,
+        };
+        Retention {
+            window,
+            spill: self.spill.clone(),
+        }
+    }
+}
+/// Parses `CODE:PLACES` of `--currencies`
+fn parse_currency(s: &str) -> Result<(Currency, u32), String> {
+    let (code, places) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected CODE:PLACES, got {:?}", s))?;
+    let currency: Currency = code.parse()?;
+    if currency.is_default() {
+        return Err(format!("missing currency code in {:?}", s));
+    }
+    let places = places.trim().parse().map_err(|e| format!("{}", e))?;
+    Ok((currency, places))
+}
+/// Process exit codes of the application
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// All rows are applied
+    Ok = 0,
+    /// Input or output file cannot be read or written
+    Io = 1,
+    /// Command line arguments are wrong, or a state or configuration file is invalid
+    Usage = 2,
+    /// Some rows are rejected
+    Rejects = 3,
+    /// Reconciled accounts do not match
+    Mismatch = 4,
+}
+impl From<Status> for ExitCode {
+    fn from(value: Status) -> Self {
+        ExitCode::from(value as u8)
+    }
 }
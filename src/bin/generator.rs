@@ -1,60 +1,157 @@
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::JoinHandle;
+
 use clap::Parser;
-use fastrand::Rng;
-use fastrand_contrib::RngExt;
-const TX_NAMES: [&'static str; 5] = ["deposite", "withdrawal", "dispute", "resolve", "chargeback"];
-fn main() {
+use jones_project::{
+    generator::{Amount, Config, Format, Generator, Row, Scenario, Weights},
+    model::Model,
+};
+/// Number of rows a thread formats before handing them to the writer
+const CHUNK_ROWS: usize = 64 * 1024;
+/// Number of chunks a thread can get ahead of the writer
+const CHUNKS_AHEAD: usize = 4;
+
+/// Formatted rows of a partition. `rows` are kept only when the model or tags need them
+struct Chunk {
+    text: String,
+    rows: Vec<Row>,
+}
+
+fn main() -> Result<(), csv::Error> {
     let args = Cli::parse();
-    let mut rng = Rng::with_seed(0x42);
-    let mut tx_id = 1;
-    let mut txs = vec![];
-    // print header
-    println!("type,client,tx,amount");
-    //print desposite first
-    for client_id in 1..=args.num_clients {
-        for _ in 0..4 {
-            print_fist_deposite(client_id, tx_id);
-            txs.push((client_id, tx_id));
-            tx_id += 1;
+    let mut config = Config::new(args.num_clients, args.num_lines)
+        .with_seed(args.seed)
+        .with_scenario(args.scenario);
+    if let Some(weights) = args.weights {
+        config.weights = weights;
+    }
+    if let Some(amount) = args.deposit_amount {
+        config.deposit_amount = amount;
+    }
+    if let Some(amount) = args.withdrawal_amount {
+        config.withdrawal_amount = amount;
+    }
+    config.malformed_rate = args.malformed_rate;
+    let mut out = BufWriter::with_capacity(1 << 20, std::io::stdout().lock());
+    let mut tags = match &args.tags {
+        Some(path) => {
+            let mut wtr = csv::Writer::from_path(path)?;
+            wtr.write_record(["line", "tag", "expected"])?;
+            Some(wtr)
         }
+        None => None,
+    };
+    let mut model = (args.expect.is_some() || tags.is_some()).then(Model::default);
+    let mut line = 0;
+    if let Some(header) = args.format.header() {
+        writeln!(out, "{}", header)?;
+        line += 1;
     }
-    let num_lines = args.num_lines - args.num_clients as usize;
-    // randomly print transactions
-    for _ in 0..num_lines {
-        let client_id = rng.u16(1..=args.num_clients);
-        let i = fastrand::usize(..TX_NAMES.len());
-        let kind = TX_NAMES[i];
-        match kind {
-            "deposite" | "withdrawal" => {
-                txs.push((client_id, tx_id));
-                let amount = rng.f32_range(1.5..3.0);
-                println!("{},{},{},{:.2}", kind, client_id, tx_id, amount);
-                tx_id += 1;
-            }
-            _ => {
-                let ids: Vec<u32> = txs
-                    .iter()
-                    .filter(|x| x.0 == client_id)
-                    .map(|x| x.1)
-                    .collect();
-
-                let i = fastrand::usize(..ids.len());
-                if let Some(id) = ids.get(i) {
-                    println!("{},{},{},", kind, client_id, id);
-                } else {
-                    println!("{},{},{},", kind, client_id, tx_id - 1);
+    let partitions = if args.threads > 1 {
+        config.partitions(args.threads)
+    } else {
+        vec![config]
+    };
+    let (receivers, handles): (Vec<_>, Vec<_>) = partitions
+        .into_iter()
+        .map(|config| spawn(config, args.format, model.is_some()))
+        .unzip();
+    // chunks are taken from partitions in turn, so the output only depends on the seed and the number of threads
+    let mut active: Vec<&Receiver<Chunk>> = receivers.iter().collect();
+    while !active.is_empty() {
+        let mut i = 0;
+        while i < active.len() {
+            let Ok(chunk) = active[i].recv() else {
+                active.remove(i);
+                continue;
+            };
+            out.write_all(chunk.text.as_bytes())?;
+            for row in chunk.rows {
+                line += 1;
+                let expected = match (model.as_mut(), row.transaction()) {
+                    (Some(model), Some(tx)) => model.apply(tx).err().unwrap_or_default(),
+                    _ => "parse",
+                };
+                if let (Some(wtr), Some(tag)) = (tags.as_mut(), row.tag) {
+                    wtr.write_record([&line.to_string(), tag.as_str(), expected])?;
                 }
             }
-        };
+            i += 1;
+        }
+    }
+    for handle in handles {
+        handle.join().expect("generator thread panicked");
+    }
+    out.flush()?;
+    if let Some(mut wtr) = tags {
+        wtr.flush()?;
     }
+    if let (Some(path), Some(model)) = (&args.expect, model) {
+        model.write_accounts(BufWriter::new(std::fs::File::create(path)?))?;
+    }
+    Ok(())
 }
 
-fn print_fist_deposite(client_id: u16, tx_id: u32) {
-    println!("{},{},{},{}", "deposite", client_id, tx_id, 100.0);
+/// Generates rows of the partition in a separate thread
+fn spawn(config: Config, format: Format, keep_rows: bool) -> (Receiver<Chunk>, JoinHandle<()>) {
+    let (tx, rx) = sync_channel(CHUNKS_AHEAD);
+    let handle = std::thread::spawn(move || {
+        let mut generator = Generator::new(config).peekable();
+        while generator.peek().is_some() {
+            let mut chunk = Chunk {
+                text: String::with_capacity(CHUNK_ROWS * 32),
+                rows: vec![],
+            };
+            for row in generator.by_ref().take(CHUNK_ROWS) {
+                row.write(format, &mut chunk.text);
+                if keep_rows {
+                    chunk.rows.push(row);
+                }
+            }
+            if tx.send(chunk).is_err() {
+                break;
+            }
+        }
+    });
+    (rx, handle)
 }
+
 #[derive(Parser)]
 struct Cli {
-    // Number of clients
+    /// Number of clients
     num_clients: u16,
-    // Number of lines to generate
+    /// Number of lines to generate
     num_lines: usize,
+    /// Seed of every random choice, the same seed produces the same file
+    #[arg(long, default_value_t = 0x42)]
+    seed: u64,
+    /// Preset of weights and amounts
+    #[arg(long, value_enum, default_value_t = Scenario::Default)]
+    scenario: Scenario,
+    /// Weights of transaction kinds, e.g. `deposit=5,withdrawal=3,dispute=1,resolve=1,chargeback=1`
+    #[arg(long)]
+    weights: Option<Weights>,
+    /// Distribution of deposit amounts: `fixed:<x>`, `uniform:<min>:<max>` or `normal:<mean>:<sigma>`
+    #[arg(long)]
+    deposit_amount: Option<Amount>,
+    /// Distribution of withdrawal amounts, same format as `--deposit-amount`
+    #[arg(long)]
+    withdrawal_amount: Option<Amount>,
+    /// Write the expected `client,available,held,total,locked` output of the generated file
+    #[arg(long)]
+    expect: Option<PathBuf>,
+    /// Share of random rows replaced by malformed or adversarial ones, from 0 to 1
+    #[arg(long, default_value_t = 0.0)]
+    malformed_rate: f32,
+    /// Write `line,tag,expected` of every injected row, `expected` is the reject code or empty if the row is applied
+    #[arg(long)]
+    tags: Option<PathBuf>,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    format: Format,
+    /// Number of threads generating rows, clients are split between them, at most one thread per client
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
 }
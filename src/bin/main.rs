@@ -1,41 +1,147 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
 use clap::Parser;
-use jones_project::{app::CliApp, db::Db, error::Error, transaction::Transaction};
-fn main() -> Result<(), Error> {
+use jones_project::{
+    app::{CliApp, Status},
+    engine::{CsvRejects, Engine, Report},
+    error::Error,
+    fraud::Fraud,
+    rules::RuleChain,
+    screening::Screening,
+    state::State,
+    statement::Statement,
+    threshold::LargeReport,
+};
+fn main() -> ExitCode {
     let args = CliApp::parse();
     if args.logger {
         env_logger::init();
     }
-    let Some(filepath) = args.filename else {
-        log::error!("The path to CSV file with transactions is requered");
-        return Err(Error::ArgsParse);
+    let Some(filepath) = &args.filename else {
+        eprintln!("{}", Error::ArgsParse);
+        return Status::Usage.into();
+    };
+    let configs = (
+        load(args.rules.as_deref(), RuleChain::from_json),
+        load(args.fraud.as_deref(), Fraud::from_json),
+        args.screening()
+            .map(|(mode, path)| {
+                Screening::from_file(mode, path).map_err(|e| format!("{}: {}", path.display(), e))
+            })
+            .transpose(),
+    );
+    let (rules, fraud, screening) = match configs {
+        (Ok(rules), Ok(fraud), Ok(screening)) => (
+            rules.unwrap_or_default(),
+            fraud.unwrap_or_default(),
+            screening.unwrap_or_default(),
+        ),
+        (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+            eprintln!("{}", e);
+            return Status::Usage.into();
+        }
+    };
+    let report = run(&args, filepath, rules, fraud, screening).and_then(|report| {
+        let mut out = BufWriter::new(std::io::stdout().lock());
+        report.write_accounts(&mut out)?;
+        out.flush()?;
+        Ok(report)
+    });
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("{}", e);
+            return failure_status(&e).into();
+        }
     };
-    let db = Db::default();
-    let read_result = match csv::Reader::from_path(&filepath) {
-        Ok(mut rdr) => {
-            for result in rdr.deserialize() {
-                match result {
-                    Ok(r) => {
-                        let record: Transaction = r;
-                        if !record.is_valid() {
-                            log::error!("Invalid record: {}", record);
-                            continue;
-                        }
-                        if let Err(e) = db.process(record.clone()) {
-                            log::error!("{} for the record {}", e, record);
-                        }
-                    }
-                    Err(e) => log::debug!("Deserialization error: {}", e),
-                }
+    match &args.stats {
+        Some(path) => {
+            let written = File::create(path).and_then(|f| {
+                serde_json::to_writer_pretty(f, &report.stats).map_err(std::io::Error::from)
+            });
+            if let Err(e) = written {
+                eprintln!("{}", e);
+                return Status::Io.into();
             }
-
-            Ok(())
         }
-        Err(e) => Err(Error::Other(e.to_string())),
+        None => eprintln!("{}", report.stats),
+    }
+    if report.stats.rejected_total() > 0 {
+        Status::Rejects.into()
+    } else {
+        Status::Ok.into()
+    }
+}
+/// Reads an optional config file, the error names the file
+fn load<T>(
+    path: Option<&Path>,
+    parse: impl FnOnce(File) -> Result<T, Error>,
+) -> Result<Option<T>, String> {
+    path.map(|path| {
+        File::open(path)
+            .map_err(Error::from)
+            .and_then(parse)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    })
+    .transpose()
+}
+/// An input file with invalid content, like a corrupt state file or one of another version,
+/// is reported like wrong arguments, so scripts can tell it from an I/O failure
+fn failure_status(e: &Error) -> Status {
+    match e {
+        Error::StateVersion(_) | Error::Json(_) | Error::Config(_) => Status::Usage,
+        _ => Status::Io,
+    }
+}
+fn run(
+    args: &CliApp,
+    filepath: &Path,
+    rules: RuleChain,
+    fraud: Fraud,
+    screening: Screening,
+) -> Result<Report, Error> {
+    let rules = match args.currency_list() {
+        Some(list) => rules.with_rule(list),
+        None => rules,
     };
-    read_result?;
-    println!("client,available,held,total,locked");
-    for (_, account) in db.accounts() {
-        println!("{}", account.to_csv_row());
+    let mut engine = Engine::new()
+        .with_screening(screening.with_lock(args.lock_blocked))
+        .with_rules(rules)
+        .with_fraud(fraud)
+        .with_input_format(args.format)
+        .with_missing_policy(args.missing_policy())
+        .with_retention(args.retention());
+    if let Some(until) = args.until() {
+        engine = engine.with_until(until);
+    }
+    if let Some(path) = &args.large_report {
+        engine = engine.with_large_report(LargeReport::new(
+            args.thresholds(),
+            BufWriter::new(File::create(path)?),
+        )?);
+    }
+    if let Some(path) = &args.statement {
+        let statement = Statement::new(BufWriter::new(File::create(path)?), args.statement_format);
+        engine = engine.with_observer(statement.with_clients(args.accont_filter.iter().copied()));
+    }
+    if let Some(path) = &args.rejects {
+        engine = engine.with_rejects(CsvRejects::new(File::create(path)?)?);
+    }
+    if let Some(path) = &args.load_state {
+        engine.load_state(State::read(BufReader::new(File::open(path)?))?)?;
+    }
+    if filepath == Path::new("-") {
+        engine.process_reader(std::io::stdin().lock())?;
+    } else {
+        engine.process_reader(File::open(filepath)?)?;
+    }
+    if let Some(path) = &args.save_state {
+        let mut out = BufWriter::new(File::create(path)?);
+        engine.export_state()?.write(&mut out)?;
+        out.flush()?;
     }
-    Ok(())
+    engine.finish()
 }
@@ -0,0 +1,79 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::Parser;
+use jones_project::{
+    app::Status,
+    engine::Engine,
+    error::Error,
+    reconcile::{read_balances, Balance, Reconciliation},
+};
+
+/// Compares account files by client and currency and reports missing, extra and mismatched accounts
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Expected accounts, e.g. the balances of the bank
+    expected: PathBuf,
+    /// Accounts to check, e.g. the output of the main binary
+    #[arg(required_unless_present = "input", conflicts_with = "input")]
+    accounts: Option<PathBuf>,
+    /// Process this transactions file and check the resulting accounts instead of an account file
+    #[arg(long)]
+    input: Option<PathBuf>,
+    /// Largest absolute difference of amounts that still matches
+    #[arg(long, default_value_t = 0.0001)]
+    tolerance: f32,
+    /// Write the report to this file instead of the standard output
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let args = Cli::parse();
+    let result = run(&args).and_then(|result| {
+        match &args.report {
+            Some(path) => result.write_report(File::create(path)?)?,
+            None => result.write_report(std::io::stdout().lock())?,
+        }
+        Ok(result)
+    });
+    match result {
+        Ok(result) => {
+            eprintln!("{}", result);
+            if result.is_clean() {
+                Status::Ok.into()
+            } else {
+                Status::Mismatch.into()
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            Status::Io.into()
+        }
+    }
+}
+
+fn run(args: &Cli) -> Result<Reconciliation, Error> {
+    let expected = read(&args.expected)?;
+    let actual = match (&args.accounts, &args.input) {
+        (Some(path), _) => read(path)?,
+        (_, Some(path)) => {
+            let mut engine = Engine::new();
+            engine.process_reader(File::open(path)?)?;
+            engine
+                .finish()?
+                .accounts
+                .iter()
+                .flat_map(Balance::from_account)
+                .collect()
+        }
+        _ => return Err(Error::ArgsParse),
+    };
+    Ok(Reconciliation::new(&actual, &expected, args.tolerance))
+}
+
+fn read(path: &Path) -> Result<Vec<Balance>, Error> {
+    read_balances(File::open(path)?)
+}
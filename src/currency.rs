@@ -0,0 +1,79 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Maximum length of a currency code
+const MAX_LEN: usize = 8;
+
+/// Currency code of up to 8 ASCII letters or digits stored in uppercase, e.g. `USD` or `USDT`.
+/// The default value is the currency of rows without one, it is written as an empty string
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; MAX_LEN]);
+impl Currency {
+    /// Currency of rows without one
+    pub const NONE: Currency = Currency([0; MAX_LEN]);
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+    pub fn as_str(&self) -> &str {
+        let len = self.0.iter().position(|b| *b == 0).unwrap_or(MAX_LEN);
+        std::str::from_utf8(&self.0[..len]).unwrap_or_default()
+    }
+    pub fn to_bytes(self) -> [u8; MAX_LEN] {
+        self.0
+    }
+    /// Returns `None` if the bytes are not a valid code
+    pub fn from_bytes(bytes: [u8; MAX_LEN]) -> Option<Self> {
+        let currency = Currency(bytes);
+        (currency.as_str().parse() == Ok(currency)).then_some(currency)
+    }
+}
+impl FromStr for Currency {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() > MAX_LEN || !s.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(format!("invalid currency {:?}", s));
+        }
+        let mut code = [0; MAX_LEN];
+        for (dst, src) in code.iter_mut().zip(s.bytes()) {
+            *dst = src.to_ascii_uppercase();
+        }
+        Ok(Currency(code))
+    }
+}
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Currency;
+
+    #[test]
+    fn test_parse() {
+        let usd: Currency = " usd ".parse().unwrap();
+        assert_eq!("USD", usd.to_string());
+        assert_eq!(Currency::default(), "".parse().unwrap());
+        assert!(Currency::default().is_default());
+        assert!("US-D".parse::<Currency>().is_err());
+        assert!("TOOLONGCODE".parse::<Currency>().is_err());
+        assert_eq!(Some(usd), Currency::from_bytes(usd.to_bytes()));
+        assert_eq!(None, Currency::from_bytes([b'a', 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!("\"USD\"", serde_json::to_string(&usd).unwrap());
+    }
+}
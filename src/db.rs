@@ -1,128 +1,693 @@
-use crate::{account::Account, error::Error, transaction::*};
+use crate::{
+    account::Account,
+    currency::Currency,
+    error::Error,
+    observer::{Change, Observer, Observers},
+    retention::{CurrencyTable, History, Retention, SpillFile, TxRecord},
+    snapshot::Snapshots,
+    state::{State, StoredTx},
+    threshold::{LargeReport, LargeRow},
+    transaction::*,
+};
 use std::cell::RefCell;
-use std::collections::HashMap;
-thread_local! {
-    /// Account table
-    static ACC: RefCell<HashMap<u16, Account>> = Default::default();
-    /// Transaction table
-    static TX: RefCell<HashMap<u16, HashMap<u32, Transaction>>> = Default::default();
+use std::collections::{HashMap, HashSet};
+/// Defines how dispute, resolve and chargeback rows referring to an unknown transaction are handled
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum MissingPolicy {
+    /// The row is reported as `TransactionError::NotFound`
+    #[default]
+    Reject,
+    /// The row is skipped without an error
+    Ignore,
 }
-///Represents implementation of DB that contains account and transaction information
+///Represents implementation of DB that contains account and transaction information.
+/// Every `Db` owns its tables, so several databases can be used on one thread or moved between threads
 #[derive(Debug, Default)]
-pub struct Db {}
+pub struct Db {
+    missing: MissingPolicy,
+    retention: Retention,
+    observers: Observers,
+    large: Option<LargeReport>,
+    snapshot_interval: Option<u64>,
+    /// Account table
+    acc: RefCell<HashMap<u16, Account>>,
+    /// Transaction table indexed by globally unique transaction id
+    tx: RefCell<HashMap<u32, TxRecord>>,
+    /// Currencies the records of the transaction table and the spill file refer to
+    currencies: RefCell<CurrencyTable>,
+    /// Order of stored transactions and the ones evicted out of the dispute window
+    history: RefCell<History>,
+    /// Account states recorded for point-in-time queries
+    snapshots: RefCell<Snapshots>,
+    /// First I/O error of the spill file or the large transaction report since it was taken,
+    /// kept apart from the results of rows
+    failure: RefCell<Option<Error>>,
+}
 impl Db {
+    pub fn with_missing_policy(self, missing: MissingPolicy) -> Self {
+        Db { missing, ..self }
+    }
+    pub fn with_retention(self, retention: Retention) -> Self {
+        Db { retention, ..self }
+    }
+    /// Registers an observer of processed rows, observers are called in the order they are registered
+    pub fn with_observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+    /// Reports deposits and withdrawals crossing the thresholds of the report
+    pub fn with_large_report(self, large: LargeReport) -> Self {
+        Db {
+            large: Some(large),
+            ..self
+        }
+    }
+    /// Records the funds changed by every row for `Db::account_at` and the changed accounts in full every `interval` rows
+    pub fn with_snapshots(self, interval: u64) -> Self {
+        Db {
+            snapshot_interval: Some(interval.max(1)),
+            ..self
+        }
+    }
+    /// Processes a row, the large transaction report refers to it by its sequence number
     pub fn process(&self, tx: Transaction) -> Result<(), Error> {
-        match tx.kind {
-            TransactionKind::Deposit if tx.amount.is_some() => {
-                self.account_deposit(tx.client_id, tx.amount.unwrap())?;
-                self.set_tx(tx.client_id, tx.with_state(TransactionState::Completed));
+        self.process_line(self.seq() + 1, tx)
+    }
+    /// Processes a row read from the input `line`, which is written to the large transaction report
+    pub fn process_line(&self, line: u64, tx: Transaction) -> Result<(), Error> {
+        self.history.borrow_mut().seq += 1;
+        let client_id = tx.client_id;
+        let row = (tx.kind, tx.tx, tx.currency);
+        let large = self.large.as_ref().map(|_| (tx.kind, tx.client_id, tx.tx));
+        let ret = if self.observers.is_empty() {
+            self.apply(tx)
+        } else {
+            self.apply_observed(tx)
+        };
+        if let Some(interval) = self.snapshot_interval {
+            self.record_snapshot(client_id, ret.is_ok().then_some(row), interval);
+        }
+        if let (Ok(()), Some((kind, client_id, tx_id))) = (&ret, large) {
+            if matches!(kind, TransactionKind::Deposit | TransactionKind::Withdrawal) {
+                if let Err(e) = self.report_large(line, client_id, tx_id) {
+                    self.fail(e);
+                }
+            }
+        }
+        if let Err(e) = self.evict() {
+            self.fail(e);
+        }
+        ret
+    }
+    /// Takes the I/O error that happened after a row was processed, e.g. when the spill file or the report cannot be written.
+    /// The result of `Db::process` is the result of the row itself, so the row is applied even if this returns an error;
+    /// the tables stay consistent, but the caller should stop processing
+    pub fn take_failure(&self) -> Option<Error> {
+        self.failure.take()
+    }
+    fn fail(&self, e: Error) {
+        log::error!("{}", e);
+        self.failure.borrow_mut().get_or_insert(e);
+    }
+    /// Copies accounts, stored transactions with their state and the dispute window.
+    /// Transactions in the spill file are read back, so the state does not depend on it
+    pub fn export_state(&self) -> Result<State, Error> {
+        let mut accounts: Vec<Account> = self.snapshot().into_values().collect();
+        accounts.sort_by_key(|a| a.client_id());
+        let mut history = self.history.borrow_mut();
+        let db = self.tx.borrow();
+        // a transaction loaded back from the spill file can be queued twice
+        let mut seen = HashSet::new();
+        let mut transactions: Vec<StoredTx> = history
+            .queue
+            .iter()
+            .filter(|(_, tx_id)| seen.insert(*tx_id))
+            .filter_map(|(seq, tx_id)| {
+                db.get(tx_id)
+                    .map(|record| StoredTx::new(&self.to_transaction(*tx_id, *record), Some(*seq)))
+            })
+            .collect();
+        // without a dispute window transactions are not queued
+        let mut untracked: Vec<StoredTx> = db
+            .iter()
+            .filter(|(tx_id, _)| !seen.contains(*tx_id))
+            .map(|(tx_id, record)| StoredTx::new(&self.to_transaction(*tx_id, *record), None))
+            .collect();
+        untracked.sort_by_key(|t| t.tx);
+        transactions.extend(untracked);
+        let evicted: Vec<u32> = history.evicted.iter().collect();
+        let mut spilled = vec![];
+        if let Some(spill) = history.spill.as_mut() {
+            // the spill file has outdated copies of the transactions loaded back from it
+            for tx_id in evicted.iter().filter(|id| !db.contains_key(id)) {
+                if let Some(record) = spill.read(*tx_id)? {
+                    spilled.push(StoredTx::new(&self.to_transaction(*tx_id, record), None));
+                }
+            }
+        }
+        Ok(State {
+            seq: history.seq,
+            accounts,
+            transactions,
+            evicted,
+            spilled,
+            ..State::default()
+        })
+    }
+    /// Replaces the tables with the state. Spilled transactions are written to the spill file of the retention,
+    /// or are stored in memory again if there is no spill file
+    pub fn load_state(&self, state: State) -> Result<(), Error> {
+        self.clean();
+        self.acc.replace(
+            state
+                .accounts
+                .into_iter()
+                .map(|acc| (acc.client_id(), acc))
+                .collect(),
+        );
+        if self.snapshot_interval.is_some() {
+            let mut snapshots = self.snapshots.borrow_mut();
+            for acc in self.acc.borrow().values() {
+                snapshots.record(state.seq, acc);
+            }
+        }
+        let mut history = self.history.borrow_mut();
+        let mut db = self.tx.borrow_mut();
+        history.seq = state.seq;
+        for tx_id in state.evicted {
+            history.evicted.insert(tx_id);
+        }
+        let tracked = self.retention.window.is_some();
+        for t in &state.transactions {
+            db.insert(t.tx, self.record(&t.to_transaction())?);
+            if tracked {
+                history.queue.push_back((t.seq.unwrap_or(state.seq), t.tx));
+            }
+        }
+        match &self.retention.spill {
+            Some(path) if !state.spilled.is_empty() => {
+                let spill = history.spill.insert(SpillFile::create(path)?);
+                for t in &state.spilled {
+                    spill.write(t.tx, self.record(&t.to_transaction())?)?;
+                }
+            }
+            _ => {
+                for t in &state.spilled {
+                    db.insert(t.tx, self.record(&t.to_transaction())?);
+                    if tracked {
+                        history.queue.push_back((state.seq, t.tx));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Number of rows processed since the tables were cleaned
+    pub fn seq(&self) -> u64 {
+        self.history.borrow().seq
+    }
+    /// Account of the client as of the row `seq`, `None` if it did not exist.
+    /// Every change of an account is recorded, so any row is answered exactly;
+    /// without snapshots only the current row can be queried
+    pub fn account_at(&self, client_id: u16, seq: u64) -> Option<Account> {
+        if seq >= self.seq() {
+            return self.account(client_id);
+        }
+        self.snapshots.borrow().at(client_id, seq)
+    }
+    /// Records the account of the processed row, `applied` is the kind, id and currency of an applied row.
+    /// The changed accounts are recorded in full at the end of an interval
+    fn record_snapshot(
+        &self,
+        client_id: u16,
+        applied: Option<(TransactionKind, u32, Currency)>,
+        interval: u64,
+    ) {
+        let seq = self.seq();
+        // disputes, resolves and chargebacks move the funds of the referenced transaction
+        let currency = applied.and_then(|(kind, tx_id, currency)| match kind {
+            TransactionKind::Deposit | TransactionKind::Withdrawal => Some(currency),
+            _ => self.transaction(tx_id).map(|t| t.currency),
+        });
+        let accounts = self.acc.borrow();
+        let mut snapshots = self.snapshots.borrow_mut();
+        if let Some(acc) = accounts.get(&client_id) {
+            snapshots.record_row(seq, acc, currency);
+        }
+        if seq.is_multiple_of(interval) {
+            snapshots.compact(seq, &accounts);
+        }
+    }
+    /// Notifies observers of a row rejected before it is processed, e.g. by a validation rule
+    pub fn reject(&self, tx: &Transaction, err: &Error) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let account = self.account(tx.client_id);
+        let change = Change {
+            client_id: tx.client_id,
+            tx: Some(tx),
+            before: account.as_ref(),
+            after: account.as_ref(),
+        };
+        self.observers.notify(|o| o.on_rejected(&change, err));
+    }
+    /// Flushes the report of large transactions and the observers, returns the first error
+    pub fn flush(&self) -> Result<(), Error> {
+        let observed = self.observers.flush();
+        match &self.large {
+            Some(large) => large.flush().and(observed),
+            None => observed,
+        }
+    }
+    /// Writes the applied deposit or withdrawal to the report if it crosses a threshold.
+    /// The aggregate is reported on the row that takes it above the threshold
+    fn report_large(&self, line: u64, client_id: u16, tx_id: u32) -> Result<(), Error> {
+        let Some(large) = &self.large else {
+            return Ok(());
+        };
+        let Some((amount, currency)) = self
+            .transaction(tx_id)
+            .and_then(|t| t.amount.map(|amount| (amount, t.currency)))
+        else {
+            return Ok(());
+        };
+        let thresholds = large.thresholds();
+        if thresholds.amount.is_some_and(|limit| amount > limit) {
+            large.write(&LargeRow {
+                line,
+                rule: "amount",
+                client: client_id,
+                txs: vec![(tx_id, amount)],
+                aggregate: amount,
+            })?;
+        }
+        if let Some(limit) = thresholds.aggregate {
+            large.push(self.seq(), tx_id);
+            let txs = self.recent_transactions(client_id, currency);
+            let aggregate: f32 = txs.iter().map(|(_, amount)| amount).sum();
+            if aggregate > limit && aggregate - amount <= limit {
+                large.write(&LargeRow {
+                    line,
+                    rule: "aggregate",
+                    client: client_id,
+                    txs,
+                    aggregate,
+                })?;
+            }
+        }
+        Ok(())
+    }
+    /// Ids and amounts of the client's deposits and withdrawals in `currency` within the aggregate window
+    /// of the large transaction report, oldest first. Transactions evicted out of the dispute window are not included
+    pub fn recent_transactions(&self, client_id: u16, currency: Currency) -> Vec<(u32, f32)> {
+        let Some(large) = &self.large else {
+            return vec![];
+        };
+        let Some(currency) = self.currencies.borrow().find(currency) else {
+            return vec![];
+        };
+        let db = self.tx.borrow();
+        large
+            .recent()
+            .into_iter()
+            .filter_map(|tx_id| {
+                db.get(&tx_id)
+                    .filter(|r| r.client_id == client_id && r.currency == currency)
+                    .map(|r| (tx_id, r.amount))
+            })
+            .collect()
+    }
+    /// Applies the row and notifies observers with the account state before and after it
+    fn apply_observed(&self, tx: Transaction) -> Result<(), Error> {
+        let before = self.account(tx.client_id);
+        let ret = self.apply(tx.clone());
+        let after = self.account(tx.client_id);
+        let change = Change {
+            client_id: tx.client_id,
+            tx: Some(&tx),
+            before: before.as_ref(),
+            after: after.as_ref(),
+        };
+        match &ret {
+            Ok(()) => {
+                self.observers.notify(|o| o.on_applied(&change));
+                let disputed = (tx.kind == TransactionKind::Dispute)
+                    .then(|| self.transaction(tx.tx))
+                    .flatten()
+                    .filter(|t| t.state == TransactionState::Dispute);
+                if let Some(disputed) = disputed {
+                    self.observers
+                        .notify(|o| o.on_dispute_opened(&change, &disputed));
+                }
+                let was_locked = before.as_ref().is_some_and(|a| a.is_locked());
+                if !was_locked && after.as_ref().is_some_and(|a| a.is_locked()) {
+                    self.observers.notify(|o| o.on_account_locked(&change));
+                }
+            }
+            Err(e) => self.observers.notify(|o| o.on_rejected(&change, e)),
+        }
+        ret
+    }
+    fn apply(&self, tx: Transaction) -> Result<(), Error> {
+        match (tx.kind, tx.amount) {
+            (TransactionKind::Deposit, Some(amount)) => {
+                self.check_unique(&tx.tx)?;
+                let record = self.record(&tx.clone().with_state(TransactionState::Completed))?;
+                self.account_deposit(tx.client_id, tx.currency, amount)?;
+                self.track(tx.tx);
+                self.tx.borrow_mut().insert(tx.tx, record);
                 Ok(())
             }
-            TransactionKind::Withdrawal if tx.amount.is_some() => {
-                self.account_withdraw(tx.client_id, tx.amount.unwrap())?;
-                self.set_tx(tx.client_id, tx.with_state(TransactionState::Completed));
+            (TransactionKind::Withdrawal, Some(amount)) => {
+                self.check_unique(&tx.tx)?;
+                let record = self.record(&tx.clone().with_state(TransactionState::Completed))?;
+                self.account_withdraw(tx.client_id, tx.currency, amount)?;
+                self.track(tx.tx);
+                self.tx.borrow_mut().insert(tx.tx, record);
                 Ok(())
             }
-            TransactionKind::Dispute if tx.amount.is_none() => {
-                if let Some(t) = self.get_tx(&tx.client_id, &tx.tx) {
-                    if t.can_dispute() {
-                        self.account_dispute(tx.client_id, t.amount.unwrap(), t.kind.is_deposit())?;
-                        self.set_tx(tx.client_id, t.with_state(TransactionState::Dispute));
+            (TransactionKind::Dispute, None) => {
+                let Some(t) = self.find_tx(&tx)? else {
+                    return Ok(());
+                };
+                match t.amount.filter(|_| t.can_dispute()) {
+                    Some(amount) => {
+                        self.account_dispute(
+                            tx.client_id,
+                            t.currency,
+                            amount,
+                            t.kind.is_deposit(),
+                        )?;
+                        self.set_tx(t.with_state(TransactionState::Dispute))?;
                         Ok(())
-                    } else {
-                        Err(TransactionError::Dispute.into())
                     }
-                } else {
-                    Err(TransactionError::NotFound.into())
+                    None => Err(TransactionError::Dispute.into()),
                 }
             }
-            TransactionKind::Resolve if tx.amount.is_none() => {
-                if let Some(t) = self.get_tx(&tx.client_id, &tx.tx) {
-                    if t.can_resolve() {
-                        self.account_resolve(tx.client_id, t.amount.unwrap())?;
-                        self.set_tx(tx.client_id, t.with_state(TransactionState::Resolved));
-                    } else {
-                        return Err(TransactionError::Resolve.into());
+            (TransactionKind::Resolve, None) => {
+                let Some(t) = self.find_tx(&tx)? else {
+                    return Ok(());
+                };
+                match t.amount.filter(|_| t.can_resolve()) {
+                    Some(amount) => {
+                        self.account_resolve(tx.client_id, t.currency, amount)?;
+                        self.set_tx(t.with_state(TransactionState::Resolved))?;
+                        Ok(())
                     }
+                    None => Err(TransactionError::Resolve.into()),
                 }
-                Ok(())
             }
-            TransactionKind::Chargeback if tx.amount.is_none() => {
-                if let Some(t) = self.get_tx(&tx.client_id, &tx.tx) {
-                    if t.can_chargeback() {
-                        self.account_chargeback(tx.client_id, t.amount.unwrap())?;
-                        self.set_tx(tx.client_id, t.with_state(TransactionState::Chargeback));
-                    } else {
-                        return Err(TransactionError::Chargeback.into());
+            (TransactionKind::Chargeback, None) => {
+                let Some(t) = self.find_tx(&tx)? else {
+                    return Ok(());
+                };
+                match t.amount.filter(|_| t.can_chargeback()) {
+                    Some(amount) => {
+                        self.account_chargeback(tx.client_id, t.currency, amount)?;
+                        self.set_tx(t.with_state(TransactionState::Chargeback))?;
+                        Ok(())
                     }
+                    None => Err(TransactionError::Chargeback.into()),
                 }
-                Ok(())
             }
             _ => Err(TransactionError::UnExpectedAmount.into()),
         }
     }
-    fn get_tx(&self, client_id: &u16, tx_id: &u32) -> Option<Transaction> {
-        TX.with_borrow_mut(|db| {
-            db.get(&client_id)
-                .map(|c| c.get(&tx_id))
-                .flatten()
-                .map(|t| t.clone())
+    /// Looks up the transaction referenced by a dispute, resolve or chargeback row.
+    /// `Ok(None)` means the reference is dangling and should be skipped according to `MissingPolicy::Ignore`
+    fn find_tx(&self, tx: &Transaction) -> Result<Option<Transaction>, Error> {
+        let Some(t) = self.get_tx(tx.tx)? else {
+            if self.is_evicted(tx.tx) {
+                return Err(TransactionError::Expired.into());
+            }
+            return match self.missing {
+                MissingPolicy::Reject => Err(TransactionError::NotFound.into()),
+                MissingPolicy::Ignore => {
+                    log::debug!("Skip reference to unknown transaction {}", tx);
+                    Ok(None)
+                }
+            };
+        };
+        if t.client_id != tx.client_id {
+            return Err(TransactionError::ClientMismatch.into());
+        }
+        if !self.tx.borrow().contains_key(&t.tx) {
+            // the transaction is loaded back from the spill file and gets a new place in the window
+            self.track(t.tx);
+        }
+        Ok(Some(t))
+    }
+    /// Returns a stored deposit or withdrawal by its id regardless of the client it belongs to
+    pub fn transaction(&self, tx_id: u32) -> Option<Transaction> {
+        self.get_tx(tx_id).unwrap_or_else(|e| {
+            log::error!("{} while reading transaction #{}", e, tx_id);
+            None
         })
     }
-    fn set_tx(&self, client_id: u16, tx: Transaction) -> Option<Transaction> {
-        TX.with_borrow_mut(|db| db.entry(client_id).or_default().insert(tx.tx, tx))
+    fn get_tx(&self, tx_id: u32) -> Result<Option<Transaction>, Error> {
+        if let Some(record) = self.tx.borrow().get(&tx_id).copied() {
+            return Ok(Some(self.to_transaction(tx_id, record)));
+        }
+        let mut history = self.history.borrow_mut();
+        let history = &mut *history;
+        match history.spill.as_mut() {
+            Some(spill) if history.evicted.contains(tx_id) => {
+                Ok(spill.read(tx_id)?.map(|r| self.to_transaction(tx_id, r)))
+            }
+            _ => Ok(None),
+        }
     }
-    fn account_deposit(&self, client_id: u16, amount: f32) -> Result<(), Error> {
-        ACC.with_borrow_mut(|db| {
-            db.entry(client_id)
-                .or_insert(Account::new(client_id))
-                .deposit(amount)
-        })
+    fn is_evicted(&self, tx_id: u32) -> bool {
+        self.history.borrow().evicted.contains(tx_id)
     }
-    fn account_withdraw(&self, client_id: u16, amount: f32) -> Result<(), Error> {
-        ACC.with_borrow_mut(|db| {
-            db.entry(client_id)
-                .or_insert(Account::new(client_id))
-                .withdraw(amount)
-        })
+    /// Transaction ids are unique across all clients, so a new deposit or withdrawal cannot reuse an id
+    fn check_unique(&self, tx_id: &u32) -> Result<(), Error> {
+        if self.tx.borrow().contains_key(tx_id) || self.is_evicted(*tx_id) {
+            Err(TransactionError::Duplicate.into())
+        } else {
+            Ok(())
+        }
     }
-    fn account_dispute(&self, client_id: u16, amount: f32, is_deposit: bool) -> Result<(), Error> {
-        ACC.with_borrow_mut(|db| {
-            db.entry(client_id)
-                .or_insert(Account::new(client_id))
-                .dispute(amount, is_deposit)
-        })
+    fn set_tx(&self, tx: Transaction) -> Result<(), Error> {
+        let record = self.record(&tx)?;
+        self.tx.borrow_mut().insert(tx.tx, record);
+        Ok(())
     }
-    fn account_resolve(&self, client_id: u16, amount: f32) -> Result<(), Error> {
-        ACC.with_borrow_mut(|db| {
-            db.entry(client_id)
-                .or_insert(Account::new(client_id))
-                .resolve(amount)
-        })
+    /// Record of the transaction with its currency added to the currency table
+    fn record(&self, tx: &Transaction) -> Result<TxRecord, Error> {
+        let currency = self.currencies.borrow_mut().index(tx.currency);
+        match currency {
+            Some(currency) => Ok(TxRecord::new(tx, currency)),
+            None => Err(TransactionError::CurrencyLimit.into()),
+        }
     }
-    fn account_chargeback(&self, client_id: u16, amount: f32) -> Result<(), Error> {
-        ACC.with_borrow_mut(|db| {
-            db.entry(client_id)
-                .or_insert(Account::new(client_id))
-                .chargeback(amount)
-        })
+    fn to_transaction(&self, tx_id: u32, record: TxRecord) -> Transaction {
+        record.to_transaction(tx_id, self.currencies.borrow().get(record.currency))
+    }
+    /// Puts the transaction at the end of the dispute window, transactions are not queued without a window
+    fn track(&self, tx_id: u32) {
+        if self.retention.window.is_none() {
+            return;
+        }
+        let mut history = self.history.borrow_mut();
+        let seq = history.seq;
+        history.queue.push_back((seq, tx_id));
+    }
+    /// Moves transactions out of the dispute window to the spill file, or drops them if there is no spill file
+    fn evict(&self) -> Result<(), Error> {
+        let Some(window) = self.retention.window else {
+            return Ok(());
+        };
+        let mut history = self.history.borrow_mut();
+        let history = &mut *history;
+        let mut db = self.tx.borrow_mut();
+        // every queued transaction is checked at most once, so open disputes cannot loop forever
+        let mut remaining = history.queue.len();
+        while remaining > 0 && history.is_exceeded(&window, db.len()) {
+            remaining -= 1;
+            let Some((seq, tx_id)) = history.queue.pop_front() else {
+                break;
+            };
+            let Some(record) = db.get(&tx_id).copied() else {
+                continue;
+            };
+            if record.state() == TransactionState::Dispute {
+                // an open dispute has to stay resolvable
+                history.queue.push_back((history.seq, tx_id));
+                continue;
+            }
+            if let Some(path) = &self.retention.spill {
+                let written = match history.spill.as_mut() {
+                    Some(spill) => spill.write(tx_id, record),
+                    None => SpillFile::create(path)
+                        .and_then(|spill| history.spill.insert(spill).write(tx_id, record)),
+                };
+                if let Err(e) = written {
+                    // the transaction stays in memory at its place in the window
+                    history.queue.push_front((seq, tx_id));
+                    return Err(e);
+                }
+            }
+            db.remove(&tx_id);
+            history.evicted.insert(tx_id);
+            log::debug!("Transaction #{} is evicted", tx_id);
+        }
+        Ok(())
+    }
+    fn account_deposit(
+        &self,
+        client_id: u16,
+        currency: Currency,
+        amount: f32,
+    ) -> Result<(), Error> {
+        self.acc
+            .borrow_mut()
+            .entry(client_id)
+            .or_insert(Account::new(client_id))
+            .deposit(currency, amount)
+    }
+    fn account_withdraw(
+        &self,
+        client_id: u16,
+        currency: Currency,
+        amount: f32,
+    ) -> Result<(), Error> {
+        self.acc
+            .borrow_mut()
+            .entry(client_id)
+            .or_insert(Account::new(client_id))
+            .withdraw(currency, amount)
+    }
+    fn account_dispute(
+        &self,
+        client_id: u16,
+        currency: Currency,
+        amount: f32,
+        is_deposit: bool,
+    ) -> Result<(), Error> {
+        self.acc
+            .borrow_mut()
+            .entry(client_id)
+            .or_insert(Account::new(client_id))
+            .dispute(currency, amount, is_deposit)
+    }
+    fn account_resolve(
+        &self,
+        client_id: u16,
+        currency: Currency,
+        amount: f32,
+    ) -> Result<(), Error> {
+        self.acc
+            .borrow_mut()
+            .entry(client_id)
+            .or_insert(Account::new(client_id))
+            .resolve(currency, amount)
+    }
+    fn account_chargeback(
+        &self,
+        client_id: u16,
+        currency: Currency,
+        amount: f32,
+    ) -> Result<(), Error> {
+        self.acc
+            .borrow_mut()
+            .entry(client_id)
+            .or_insert(Account::new(client_id))
+            .chargeback(currency, amount)
+    }
+    /// Rejects further withdrawals of an existing account, observers are notified if it was not on hold
+    pub fn hold_account(&self, client_id: u16, reason: &str) {
+        let held = self.change_account(client_id, |acc| {
+            let was_held = acc.is_on_hold();
+            acc.hold(reason);
+            !was_held
+        });
+        if let Some((before, after)) = held {
+            let change = Change {
+                client_id,
+                tx: None,
+                before: Some(&before),
+                after: Some(&after),
+            };
+            self.observers.notify(|o| o.on_account_held(&change));
+        }
+    }
+    /// Locks an existing account, observers are notified if it was not locked
+    pub fn lock_account(&self, client_id: u16, reason: &str) {
+        let locked = self.change_account(client_id, |acc| {
+            let was_locked = acc.is_locked();
+            acc.lock(reason);
+            !was_locked
+        });
+        if let Some((before, after)) = locked {
+            let change = Change {
+                client_id,
+                tx: None,
+                before: Some(&before),
+                after: Some(&after),
+            };
+            self.observers.notify(|o| o.on_account_locked(&change));
+        }
+    }
+    /// Changes an existing account outside of a row. Returns the account before and after the change
+    /// if `f` reports it as an event for the observers
+    fn change_account(
+        &self,
+        client_id: u16,
+        f: impl FnOnce(&mut Account) -> bool,
+    ) -> Option<(Account, Account)> {
+        let mut acc = self.acc.borrow_mut();
+        let acc = acc.get_mut(&client_id)?;
+        let before = acc.clone();
+        let changed = f(acc);
+        if self.snapshot_interval.is_some() {
+            self.snapshots.borrow_mut().record(self.seq(), acc);
+        }
+        (changed && !self.observers.is_empty()).then(|| (before, acc.clone()))
+    }
+    /// Returns a copy of the account without taking it out of the database
+    pub fn account(&self, client_id: u16) -> Option<Account> {
+        self.acc.borrow().get(&client_id).cloned()
+    }
+    /// Returns a copy of all accounts without taking them out of the database
+    pub fn snapshot(&self) -> HashMap<u16, Account> {
+        self.acc.borrow().clone()
     }
     pub fn accounts(&self) -> HashMap<u16, Account> {
-        ACC.take()
+        self.acc.take()
     }
+    /// Takes the in-memory transaction table grouped by client
     pub fn transactions(&self) -> HashMap<u16, HashMap<u32, Transaction>> {
-        TX.take()
+        self.tx
+            .take()
+            .into_iter()
+            .fold(HashMap::new(), |mut clients, (tx_id, record)| {
+                clients
+                    .entry(record.client_id)
+                    .or_default()
+                    .insert(tx_id, self.to_transaction(tx_id, record));
+                clients
+            })
     }
     pub fn clean(&self) {
-        ACC.set(Default::default());
-        TX.set(Default::default());
+        self.acc.take();
+        self.tx.take();
+        self.currencies.take();
+        self.history.take();
+        self.snapshots.take();
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Transaction, TransactionKind, TransactionState, TX};
-    use crate::{account::AccountError, db::Db, transaction::TransactionError};
+    use super::{Currency, Transaction, TransactionKind, TransactionState};
+    use crate::{
+        account::AccountError,
+        db::{Db, MissingPolicy},
+        retention::{DisputeWindow, Retention},
+        transaction::TransactionError,
+    };
     const CLIENT_ID: u16 = 1;
     const DE_ID: u32 = 1;
     const WI_ID: u32 = 2;
@@ -132,6 +697,7 @@ mod tests {
             amount: Some(4.0),
             client_id: CLIENT_ID,
             tx: DE_ID,
+            currency: Currency::NONE,
             state: TransactionState::Processing,
             kind: TransactionKind::Deposit,
         }
@@ -141,6 +707,7 @@ mod tests {
             amount: Some(1.0),
             client_id: CLIENT_ID,
             tx: WI_ID,
+            currency: Currency::NONE,
             state: TransactionState::Processing,
             kind: TransactionKind::Withdrawal,
         }
@@ -150,6 +717,7 @@ mod tests {
             amount: None,
             client_id: CLIENT_ID,
             tx: WI_ID,
+            currency: Currency::NONE,
             state: TransactionState::Processing,
             kind: TransactionKind::Dispute,
         }
@@ -159,6 +727,7 @@ mod tests {
             amount: None,
             client_id: CLIENT_ID,
             tx: WI_ID,
+            currency: Currency::NONE,
             state: TransactionState::Processing,
             kind: TransactionKind::Resolve,
         }
@@ -168,13 +737,17 @@ mod tests {
             amount: None,
             client_id: CLIENT_ID,
             tx: WI_ID,
+            currency: Currency::NONE,
             state: TransactionState::Processing,
             kind: TransactionKind::Chargeback,
         }
     }
-    fn transactions_len() -> usize {
-        TX.with_borrow(|db| db.get(&CLIENT_ID).map(|t| t.len()))
-            .unwrap_or(0)
+    fn transactions_len(db: &Db) -> usize {
+        db.tx
+            .borrow()
+            .values()
+            .filter(|t| t.client_id == CLIENT_ID)
+            .count()
     }
 
     #[test]
@@ -236,24 +809,24 @@ mod tests {
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(1, transactions_len());
+        assert_eq!(1, transactions_len(&db));
         let tx = get_withdraw_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_dispute_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_dispute_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Err(TransactionError::Dispute.into()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
     }
     #[test]
     fn test_resolve_process() {
@@ -264,30 +837,30 @@ mod tests {
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(1, transactions_len());
+        assert_eq!(1, transactions_len(&db));
         let tx = get_withdraw_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_dispute_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_resolve_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_resolve_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Err(TransactionError::Resolve.into()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
     }
     #[test]
     fn test_chargeback_process() {
@@ -298,29 +871,289 @@ mod tests {
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(1, transactions_len());
+        assert_eq!(1, transactions_len(&db));
         let tx = get_withdraw_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_dispute_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_chargeback_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Ok(()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
 
         let tx = get_chargeback_tx();
         assert!(tx.is_valid());
         let result = db.process(tx);
         assert_eq!(Err(TransactionError::Chargeback.into()), result);
-        assert_eq!(2, transactions_len());
+        assert_eq!(2, transactions_len(&db));
+    }
+    #[test]
+    fn test_missing_reference_process_err() {
+        let db = Db::default();
+        db.clean();
+
+        let result = db.process(get_dispute_tx());
+        assert_eq!(Err(TransactionError::NotFound.into()), result);
+        let result = db.process(get_resolve_tx());
+        assert_eq!(Err(TransactionError::NotFound.into()), result);
+        let result = db.process(get_chargeback_tx());
+        assert_eq!(Err(TransactionError::NotFound.into()), result);
+        assert_eq!(0, transactions_len(&db));
+    }
+    #[test]
+    fn test_missing_reference_process_ignore() {
+        let db = Db::default().with_missing_policy(MissingPolicy::Ignore);
+        db.clean();
+
+        let result = db.process(get_dispute_tx());
+        assert_eq!(Ok(()), result);
+        let result = db.process(get_resolve_tx());
+        assert_eq!(Ok(()), result);
+        let result = db.process(get_chargeback_tx());
+        assert_eq!(Ok(()), result);
+        assert_eq!(0, transactions_len(&db));
+    }
+    #[test]
+    fn test_client_mismatch_process_err() {
+        let db = Db::default().with_missing_policy(MissingPolicy::Ignore);
+        db.clean();
+
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Ok(()), result);
+        let other = Transaction {
+            client_id: CLIENT_ID + 1,
+            tx: DE_ID,
+            ..get_dispute_tx()
+        };
+        let result = db.process(other.clone());
+        assert_eq!(Err(TransactionError::ClientMismatch.into()), result);
+        let other = Transaction {
+            kind: TransactionKind::Resolve,
+            ..other
+        };
+        let result = db.process(other.clone());
+        assert_eq!(Err(TransactionError::ClientMismatch.into()), result);
+        let other = Transaction {
+            kind: TransactionKind::Chargeback,
+            ..other
+        };
+        let result = db.process(other);
+        assert_eq!(Err(TransactionError::ClientMismatch.into()), result);
+    }
+    #[test]
+    fn test_duplicate_process_err() {
+        let db = Db::default();
+        db.clean();
+
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Ok(()), result);
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Err(TransactionError::Duplicate.into()), result);
+        let other = Transaction {
+            client_id: CLIENT_ID + 1,
+            ..get_deposit_tx()
+        };
+        let result = db.process(other);
+        assert_eq!(Err(TransactionError::Duplicate.into()), result);
+        let withdraw = Transaction {
+            tx: DE_ID,
+            ..get_withdraw_tx()
+        };
+        let result = db.process(withdraw);
+        assert_eq!(Err(TransactionError::Duplicate.into()), result);
+
+        let txs = db.transactions();
+        assert_eq!(1, txs.len());
+        assert!(!txs.contains_key(&(CLIENT_ID + 1)));
+    }
+    #[test]
+    fn test_transaction_lookup() {
+        let db = Db::default();
+        db.clean();
+
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Ok(()), result);
+        let other = Transaction {
+            client_id: CLIENT_ID + 1,
+            tx: WI_ID,
+            ..get_deposit_tx()
+        };
+        let result = db.process(other.clone());
+        assert_eq!(Ok(()), result);
+
+        assert_eq!(
+            Some(get_deposit_tx().with_state(TransactionState::Completed)),
+            db.transaction(DE_ID)
+        );
+        assert_eq!(
+            Some(other.with_state(TransactionState::Completed)),
+            db.transaction(WI_ID)
+        );
+        assert_eq!(None, db.transaction(WI_ID + 1));
+        let txs = db.transactions();
+        assert_eq!(2, txs.len());
+    }
+    #[test]
+    fn test_expired_process_err() {
+        let db = Db::default().with_retention(Retention {
+            window: Some(DisputeWindow::Count(1)),
+            spill: None,
+        });
+        db.clean();
+
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Ok(()), result);
+        let result = db.process(get_withdraw_tx());
+        assert_eq!(Ok(()), result);
+        assert_eq!(1, transactions_len(&db));
+        assert_eq!(None, db.transaction(DE_ID));
+
+        let dispute = Transaction {
+            tx: DE_ID,
+            ..get_dispute_tx()
+        };
+        let result = db.process(dispute);
+        assert_eq!(Err(TransactionError::Expired.into()), result);
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Err(TransactionError::Duplicate.into()), result);
+    }
+    #[test]
+    fn test_expired_by_age_keeps_dispute() {
+        let db = Db::default().with_retention(Retention {
+            window: Some(DisputeWindow::Age(1)),
+            spill: None,
+        });
+        db.clean();
+
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Ok(()), result);
+        let result = db.process(get_withdraw_tx());
+        assert_eq!(Ok(()), result);
+        let result = db.process(get_dispute_tx());
+        assert_eq!(Ok(()), result);
+        // deposit is out of the window, but disputed withdrawal stays
+        assert_eq!(1, transactions_len(&db));
+        let result = db.process(get_resolve_tx());
+        assert_eq!(Ok(()), result);
+        let deposit = Transaction {
+            tx: WI_ID + 1,
+            ..get_deposit_tx()
+        };
+        let result = db.process(deposit);
+        assert_eq!(Ok(()), result);
+        let result = db.process(get_chargeback_tx());
+        assert_eq!(Err(TransactionError::Expired.into()), result);
+    }
+    #[test]
+    fn test_spilled_process() {
+        let path = std::env::temp_dir().join(format!("db-spill-{}.bin", std::process::id()));
+        let db = Db::default().with_retention(Retention {
+            window: Some(DisputeWindow::Count(1)),
+            spill: Some(path.clone()),
+        });
+        db.clean();
+
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Ok(()), result);
+        let deposit = Transaction {
+            tx: WI_ID,
+            ..get_deposit_tx()
+        };
+        let result = db.process(deposit);
+        assert_eq!(Ok(()), result);
+        assert_eq!(1, transactions_len(&db));
+        assert_eq!(
+            Some(get_deposit_tx().with_state(TransactionState::Completed)),
+            db.transaction(DE_ID)
+        );
+
+        let dispute = Transaction {
+            tx: DE_ID,
+            ..get_dispute_tx()
+        };
+        let result = db.process(dispute);
+        assert_eq!(Ok(()), result);
+        assert_eq!(
+            Some(get_deposit_tx().with_state(TransactionState::Dispute)),
+            db.transaction(DE_ID)
+        );
+        let result = db.process(get_deposit_tx());
+        assert_eq!(Err(TransactionError::Duplicate.into()), result);
+        db.clean();
+        let _ = std::fs::remove_file(path);
+    }
+    #[test]
+    fn test_currency_process() {
+        let usd: Currency = "USD".parse().unwrap();
+        let db = Db::default();
+        db.clean();
+
+        let deposit = Transaction {
+            currency: usd,
+            ..get_deposit_tx()
+        };
+        assert_eq!(Ok(()), db.process(deposit));
+        assert_eq!(
+            Err(AccountError::Withdraw.into()),
+            db.process(get_withdraw_tx())
+        );
+        // the dispute holds the funds of the deposit currency whatever its own currency is
+        let dispute = Transaction {
+            tx: DE_ID,
+            ..get_dispute_tx()
+        };
+        assert_eq!(Ok(()), db.process(dispute));
+        let acc = db.account(CLIENT_ID).unwrap();
+        assert_eq!((0.0, 4.0), (acc.funds(usd).available, acc.funds(usd).held));
+        assert_eq!(0.0, acc.held());
+        db.clean();
+    }
+    #[test]
+    fn test_spill_failure() {
+        let db = Db::default().with_retention(Retention {
+            window: Some(DisputeWindow::Count(1)),
+            spill: Some("/nonexistent/dir/spill.bin".into()),
+        });
+        assert_eq!(Ok(()), db.process(get_deposit_tx()));
+        assert_eq!(None, db.take_failure());
+        let deposit = Transaction {
+            tx: WI_ID,
+            ..get_deposit_tx()
+        };
+        // the row is applied even though the older transaction cannot be spilled
+        assert_eq!(Ok(()), db.process(deposit));
+        assert_eq!(Some("io"), db.take_failure().as_ref().map(|e| e.code()));
+        assert_eq!(8.0, db.account(CLIENT_ID).unwrap().available());
+        assert_eq!(2, transactions_len(&db));
+        let dispute = Transaction {
+            tx: DE_ID,
+            ..get_dispute_tx()
+        };
+        assert_eq!(Ok(()), db.process(dispute));
+    }
+    #[test]
+    fn test_untracked_without_window() {
+        let db = Db::default();
+        assert_eq!(Ok(()), db.process(get_deposit_tx()));
+        assert_eq!(Ok(()), db.process(get_withdraw_tx()));
+        assert!(db.history.borrow().queue.is_empty());
+        let state = db.export_state().unwrap();
+        assert_eq!(
+            vec![(DE_ID, None), (WI_ID, None)],
+            state
+                .transactions
+                .iter()
+                .map(|t| (t.tx, t.seq))
+                .collect::<Vec<_>>()
+        );
     }
 }
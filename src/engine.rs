@@ -0,0 +1,695 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::{
+    account::Account,
+    db::{Db, MissingPolicy},
+    error::{Error, RowError},
+    fraud::{Action, Alert, Fraud},
+    observer::Observer,
+    retention::Retention,
+    rules::RuleChain,
+    screening::Screening,
+    state::State,
+    stats::Stats,
+    threshold::LargeReport,
+    transaction::Transaction,
+};
+
+/// Format of input rows
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum InputFormat {
+    /// `type,client,tx,amount` with a header row
+    #[default]
+    Csv,
+    /// One JSON object per line, e.g. `{"type":"deposit","client":1,"tx":1,"amount":1.0}`
+    Jsonl,
+}
+
+/// Condition to stop reading input rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Until {
+    /// Stop after the first row with this transaction id
+    Tx(u32),
+    /// Stop after this input line, rows after it are not read
+    Line(u64),
+}
+
+/// Destination of rejected rows, `Send` so an engine can be moved to another thread
+pub trait RejectSink: Send {
+    fn reject(&mut self, err: &RowError) -> Result<(), Error>;
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Writes `line,client,tx,code` of every rejected row
+pub struct CsvRejects<W: Write> {
+    wtr: csv::Writer<W>,
+}
+impl<W: Write> CsvRejects<W> {
+    pub fn new(w: W) -> Result<Self, Error> {
+        let mut wtr = csv::Writer::from_writer(w);
+        wtr.write_record(["line", "client", "tx", "code"])?;
+        Ok(CsvRejects { wtr })
+    }
+}
+impl<W: Write + Send> RejectSink for CsvRejects<W> {
+    fn reject(&mut self, err: &RowError) -> Result<(), Error> {
+        let field = |x: Option<String>| x.unwrap_or_default();
+        self.wtr.write_record([
+            err.line.to_string(),
+            field(err.client.map(|c| c.to_string())),
+            field(err.tx.map(|t| t.to_string())),
+            err.code().to_string(),
+        ])?;
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        self.wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Accounts and summary of processed rows
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Accounts ordered by client
+    pub accounts: Vec<Account>,
+    /// Triggered fraud rules in the order of rows
+    pub alerts: Vec<Alert>,
+    pub stats: Stats,
+}
+impl Report {
+    /// Writes accounts in the `client,available,held,total,locked` format.
+    /// If any row has a currency, a `client,currency,available,held,total,locked` row is written per client and currency
+    pub fn write_accounts(&self, mut w: impl Write) -> std::io::Result<()> {
+        if !self.accounts.iter().any(Account::has_currencies) {
+            writeln!(w, "client,available,held,total,locked")?;
+            for account in &self.accounts {
+                writeln!(w, "{}", account.to_csv_row())?;
+            }
+            return Ok(());
+        }
+        writeln!(w, "client,currency,available,held,total,locked")?;
+        for row in self.accounts.iter().flat_map(Account::to_currency_rows) {
+            writeln!(w, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads rows, validates them, applies them to `Db` and reports rejected rows.
+/// Every engine owns its tables, so engines are independent of each other and can be moved between threads
+pub struct Engine {
+    db: Db,
+    format: InputFormat,
+    rules: RuleChain,
+    screening: Screening,
+    fraud: Fraud,
+    alerts: Vec<Alert>,
+    rejects: Option<Box<dyn RejectSink>>,
+    until: Option<Until>,
+    stopped: bool,
+    stats: Stats,
+    started: Instant,
+}
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Engine {
+    pub fn new() -> Self {
+        Engine {
+            db: Db::default(),
+            format: InputFormat::default(),
+            rules: RuleChain::default(),
+            screening: Screening::default(),
+            fraud: Fraud::default(),
+            alerts: vec![],
+            rejects: None,
+            until: None,
+            stopped: false,
+            stats: Stats::default(),
+            started: Instant::now(),
+        }
+    }
+    pub fn with_input_format(self, format: InputFormat) -> Self {
+        Engine { format, ..self }
+    }
+    pub fn with_missing_policy(self, missing: MissingPolicy) -> Self {
+        Engine {
+            db: self.db.with_missing_policy(missing),
+            ..self
+        }
+    }
+    pub fn with_retention(self, retention: Retention) -> Self {
+        Engine {
+            db: self.db.with_retention(retention),
+            ..self
+        }
+    }
+    /// Replaces the default rules checked before a row is processed
+    pub fn with_rules(self, rules: RuleChain) -> Self {
+        Engine { rules, ..self }
+    }
+    /// Sets the clients whose rows are rejected with `client_blocked` before any other check
+    pub fn with_screening(self, screening: Screening) -> Self {
+        Engine { screening, ..self }
+    }
+    /// Sets the fraud rules evaluated on applied rows
+    pub fn with_fraud(self, fraud: Fraud) -> Self {
+        Engine { fraud, ..self }
+    }
+    /// Writes deposits and withdrawals crossing the thresholds of the report while rows are processed
+    pub fn with_large_report(self, large: LargeReport) -> Self {
+        Engine {
+            db: self.db.with_large_report(large),
+            ..self
+        }
+    }
+    pub fn with_observer(self, observer: impl Observer + 'static) -> Self {
+        Engine {
+            db: self.db.with_observer(observer),
+            ..self
+        }
+    }
+    pub fn with_rejects(self, sink: impl RejectSink + 'static) -> Self {
+        Engine {
+            rejects: Some(Box::new(sink)),
+            ..self
+        }
+    }
+    /// Stops reading rows when the condition is met, the following calls to process rows do nothing
+    pub fn with_until(self, until: Until) -> Self {
+        Engine {
+            until: Some(until),
+            ..self
+        }
+    }
+    /// Records every change of the accounts for `Engine::account_at`, see `Db::with_snapshots`
+    pub fn with_snapshots(self, interval: u64) -> Self {
+        Engine {
+            db: self.db.with_snapshots(interval),
+            ..self
+        }
+    }
+    /// Replaces the accounts and transactions with a state exported by an earlier run.
+    /// Call it after the retention is configured, so spilled transactions go to its spill file
+    pub fn load_state(&mut self, state: State) -> Result<(), Error> {
+        self.db.load_state(state)
+    }
+    /// Exports the accounts and transactions, call it before `Engine::finish`
+    pub fn export_state(&self) -> Result<State, Error> {
+        self.db.export_state()
+    }
+    /// Returns `true` when the `Until` condition is met
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+    /// Number of rows applied or rejected by the database, the sequence number of `Engine::account_at`.
+    /// Rows rejected by parsing, validation rules or screening are not counted
+    pub fn seq(&self) -> u64 {
+        self.db.seq()
+    }
+    /// Account of the client as of the row `seq`, see `Db::account_at`
+    pub fn account_at(&self, client_id: u16, seq: u64) -> Option<Account> {
+        self.db.account_at(client_id, seq)
+    }
+    /// Processes all rows of the reader in the configured format.
+    /// Malformed and rejected rows are reported and skipped, only I/O errors stop the processing
+    pub fn process_reader<R: Read>(&mut self, rdr: R) -> Result<(), Error> {
+        match self.format {
+            InputFormat::Csv => self.process_csv(rdr),
+            InputFormat::Jsonl => self.process_jsonl(rdr),
+        }
+    }
+    /// Processes already parsed transactions, rows are numbered from the number of rows read before
+    pub fn process_iter(
+        &mut self,
+        txs: impl IntoIterator<Item = Transaction>,
+    ) -> Result<(), Error> {
+        for tx in txs {
+            if self.is_past(self.stats.rows_read + 1) {
+                break;
+            }
+            self.stats.record_read();
+            self.process(self.stats.rows_read, tx)?;
+        }
+        Ok(())
+    }
+    /// Reads the screening list file again, returns `false` if the list has no file.
+    /// The file is also reloaded on its own when it changes while rows are processed
+    pub fn reload_screening(&mut self) -> Result<bool, Error> {
+        let reloaded = self.screening.reload()?;
+        if reloaded {
+            self.lock_screened();
+        }
+        Ok(reloaded)
+    }
+    /// Copy of the current accounts and summary, the engine can continue processing
+    pub fn snapshot(&self) -> Report {
+        let snapshot = self.db.snapshot();
+        let mut stats = self.stats.clone();
+        stats.finish(&snapshot, self.started.elapsed());
+        let mut accounts: Vec<Account> = snapshot.into_values().collect();
+        accounts.sort_by_key(|a| a.client_id());
+        Report {
+            accounts,
+            alerts: self.alerts.clone(),
+            stats,
+        }
+    }
+    /// Flushes the sinks and returns the final accounts and summary
+    pub fn finish(mut self) -> Result<Report, Error> {
+        if let Some(sink) = self.rejects.as_mut() {
+            sink.flush()?;
+        }
+        self.db.flush()?;
+        let accounts = self.db.accounts();
+        self.stats.finish(&accounts, self.started.elapsed());
+        let mut accounts: Vec<Account> = accounts.into_values().collect();
+        accounts.sort_by_key(|a| a.client_id());
+        Ok(Report {
+            accounts,
+            alerts: self.alerts,
+            stats: self.stats,
+        })
+    }
+    fn process_csv<R: Read>(&mut self, rdr: R) -> Result<(), Error> {
+        let mut rdr = csv::Reader::from_reader(rdr);
+        let headers = rdr.headers()?.clone();
+        let mut row = csv::StringRecord::new();
+        loop {
+            match rdr.read_record(&mut row) {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(e) if e.is_io_error() => return Err(e.into()),
+                Err(e) => {
+                    let line = e.position().map(|p| p.line()).unwrap_or_default();
+                    if self.is_past(line) {
+                        return Ok(());
+                    }
+                    self.stats.record_read();
+                    let err = RowError {
+                        line,
+                        client: None,
+                        tx: None,
+                        error: e.into(),
+                    };
+                    log::debug!("{}", err);
+                    self.reject(err)?;
+                    continue;
+                }
+            }
+            let line = row.position().map(|p| p.line()).unwrap_or_default();
+            if self.is_past(line) {
+                return Ok(());
+            }
+            self.stats.record_read();
+            match row.deserialize(Some(&headers)) {
+                Ok(tx) => self.process(line, tx)?,
+                Err(e) => {
+                    let err = RowError {
+                        line,
+                        client: row.get(1).and_then(|x| x.parse().ok()),
+                        tx: row.get(2).and_then(|x| x.parse().ok()),
+                        error: e.into(),
+                    };
+                    log::debug!("{}", err);
+                    self.reject(err)?;
+                }
+            }
+        }
+    }
+    /// Blank lines are skipped and not counted as rows
+    fn process_jsonl<R: Read>(&mut self, rdr: R) -> Result<(), Error> {
+        for (i, text) in BufReader::new(rdr).lines().enumerate() {
+            let text = text?;
+            let line = i as u64 + 1;
+            if self.is_past(line) {
+                break;
+            }
+            if text.trim().is_empty() {
+                continue;
+            }
+            self.stats.record_read();
+            match serde_json::from_str(&text) {
+                Ok(tx) => self.process(line, tx)?,
+                Err(e) => {
+                    let value = serde_json::from_str::<serde_json::Value>(&text).ok();
+                    let field = |name| value.as_ref()?.get(name)?.as_u64();
+                    let err = RowError {
+                        line,
+                        client: field("client").and_then(|x| x.try_into().ok()),
+                        tx: field("tx").and_then(|x| x.try_into().ok()),
+                        error: e.into(),
+                    };
+                    log::debug!("{}", err);
+                    self.reject(err)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Returns `true` if reading has to stop before the row at `line`
+    fn is_past(&mut self, line: u64) -> bool {
+        if let Some(Until::Line(last)) = self.until {
+            self.stopped |= line > last;
+        }
+        self.stopped
+    }
+    fn process(&mut self, line: u64, tx: Transaction) -> Result<(), Error> {
+        let tx_id = tx.tx;
+        let ret = self.process_row(line, tx);
+        if self.until == Some(Until::Tx(tx_id)) {
+            self.stopped = true;
+        }
+        ret
+    }
+    fn process_row(&mut self, line: u64, tx: Transaction) -> Result<(), Error> {
+        if self.screening.refresh() {
+            self.lock_screened();
+        }
+        if self.screening.is_screened(tx.client_id) {
+            log::warn!("line {}: Client {} is blocked: {}", line, tx.client_id, tx);
+            if self.screening.is_locking()
+                && self
+                    .db
+                    .account(tx.client_id)
+                    .is_some_and(|acc| !acc.is_locked())
+            {
+                self.db.lock_account(tx.client_id, Error::Blocked.code());
+            }
+            self.db.reject(&tx, &Error::Blocked);
+            return self.reject(RowError {
+                line,
+                client: Some(tx.client_id),
+                tx: Some(tx.tx),
+                error: Error::Blocked,
+            });
+        }
+        if let Err(e) = self.rules.check(&tx) {
+            log::error!("line {}: Invalid record: {}, {}", line, tx, e);
+            self.db.reject(&tx, &e);
+            return self.reject(RowError {
+                line,
+                client: Some(tx.client_id),
+                tx: Some(tx.tx),
+                error: e,
+            });
+        }
+        let before = if self.fraud.is_empty() {
+            None
+        } else {
+            self.db.account(tx.client_id)
+        };
+        let ret = match self.db.process_line(line, tx.clone()) {
+            Ok(()) => {
+                self.stats.record_applied(&tx);
+                for alert in self.fraud.check(line, &tx, before.as_ref()) {
+                    self.alert(alert);
+                }
+                Ok(())
+            }
+            // the spill file cannot be read, this is not an error of the row
+            Err(e @ Error::Io(_)) => Err(e),
+            Err(e) => {
+                let err = RowError {
+                    line,
+                    client: Some(tx.client_id),
+                    tx: Some(tx.tx),
+                    error: e,
+                };
+                log::error!("{}", err);
+                self.reject(err)
+            }
+        };
+        // the row keeps its own result, a failed spill stops the processing
+        match self.db.take_failure() {
+            Some(e) => Err(e),
+            None => ret,
+        }
+    }
+    /// Locks existing unlocked accounts of screened clients if the screening is locking
+    fn lock_screened(&self) {
+        if !self.screening.is_locking() {
+            return;
+        }
+        for (client_id, acc) in self.db.snapshot() {
+            if !acc.is_locked() && self.screening.is_screened(client_id) {
+                self.db.lock_account(client_id, Error::Blocked.code());
+            }
+        }
+    }
+    /// Counts the alert and holds or locks the account according to the rule
+    fn alert(&mut self, alert: Alert) {
+        log::warn!(
+            "line {}: client {} triggered {}, {:?}",
+            alert.line,
+            alert.client,
+            alert.rule,
+            alert.action
+        );
+        self.stats.record_alert(alert.rule);
+        match alert.action {
+            Action::Flag => {}
+            Action::Hold => self.db.hold_account(alert.client, alert.rule),
+            Action::Lock => self.db.lock_account(alert.client, alert.rule),
+        }
+        self.alerts.push(alert);
+    }
+    /// Counts the rejected row and passes it to the sink
+    fn reject(&mut self, err: RowError) -> Result<(), Error> {
+        self.stats.record_rejected(err.code().to_string());
+        match self.rejects.as_mut() {
+            Some(sink) => sink.reject(&err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{CsvRejects, Engine, InputFormat, RejectSink, Until};
+    use crate::{
+        currency::Currency,
+        error::{Error, RowError},
+        fraud::{Action, Fraud, FraudRule},
+        observer::{Change, Observer},
+        screening::{Screening, ScreeningMode},
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    /// Collects codes of rejected rows
+    struct Codes(Arc<Mutex<Vec<(u64, &'static str)>>>);
+    impl RejectSink for Codes {
+        fn reject(&mut self, err: &RowError) -> Result<(), Error> {
+            self.0.lock().unwrap().push((err.line, err.code()));
+            Ok(())
+        }
+    }
+
+    /// Collects locks and holds as `event:client:reason`
+    struct Locks(Arc<Mutex<Vec<String>>>);
+    impl Locks {
+        fn record(&self, event: &str, change: &Change) {
+            let reason = change.after.and_then(|a| a.reason()).unwrap_or_default();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{}:{}:{}", event, change.client_id, reason));
+        }
+    }
+    impl Observer for Locks {
+        fn on_account_locked(&mut self, change: &Change) {
+            self.record("locked", change);
+        }
+        fn on_account_held(&mut self, change: &Change) {
+            self.record("held", change);
+        }
+    }
+
+    #[test]
+    fn test_independent_engines() {
+        fn assert_send<T: Send>(value: T) -> T {
+            value
+        }
+        let input = |amount| format!("type,client,tx,amount\ndeposit,1,1,{}\n", amount);
+        let mut first = assert_send(Engine::new());
+        first.process_reader(input(2.0).as_bytes()).unwrap();
+        let mut second = Engine::new();
+        second.process_reader(input(5.0).as_bytes()).unwrap();
+        let first = std::thread::spawn(move || first.finish().unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(2.0, first.accounts[0].available());
+        let second = second.finish().unwrap();
+        assert_eq!(5.0, second.accounts[0].available());
+        assert_eq!(0, second.stats.rejected_total());
+    }
+    #[test]
+    fn test_process_reader() {
+        let codes = Arc::default();
+        let mut engine = Engine::new().with_rejects(Codes(Arc::clone(&codes)));
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,3.0\ndeposit,1,x,1.0\ndeposit,2,3,-1\n";
+        engine.process_reader(input.as_bytes()).unwrap();
+        let snapshot = engine.snapshot();
+        assert_eq!(4, snapshot.stats.rows_read);
+        assert_eq!(1, snapshot.accounts.len());
+        let report = engine.finish().unwrap();
+        let mut out = vec![];
+        report.write_accounts(&mut out).unwrap();
+        assert_eq!(
+            "client,available,held,total,locked\n1,2,0,2,false\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(
+            vec![(3, "account_withdraw"), (4, "parse"), (5, "tx_invalid")],
+            *codes.lock().unwrap()
+        );
+    }
+    #[test]
+    fn test_jsonl() {
+        let mut out = vec![];
+        let mut engine = Engine::new()
+            .with_input_format(InputFormat::Jsonl)
+            .with_rejects(CsvRejects::new(std::io::Cursor::new(vec![])).unwrap());
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":2.5}
+
+{"type":"dispute","client":1,"tx":1}
+{"type":"refund","client":1,"tx":2}
+"#;
+        engine.process_reader(input.as_bytes()).unwrap();
+        let report = engine.finish().unwrap();
+        assert_eq!(3, report.stats.rows_read);
+        assert_eq!(Some(&1), report.stats.rejected.get("parse"));
+        report.write_accounts(&mut out).unwrap();
+        assert_eq!(
+            "client,available,held,total,locked\n1,0,2.5,2.5,false\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+    #[test]
+    fn test_process_iter() {
+        let mut engine = Engine::new();
+        let tx = |kind, tx, amount| Transaction {
+            kind,
+            client_id: 1,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        };
+        engine
+            .process_iter([
+                tx(TransactionKind::Deposit, 1, Some(1.0)),
+                tx(TransactionKind::Deposit, 1, Some(1.0)),
+            ])
+            .unwrap();
+        let report = engine.finish().unwrap();
+        assert_eq!(Some(&1), report.stats.applied.get("deposit"));
+        assert_eq!(Some(&1), report.stats.rejected.get("tx_duplicate"));
+        assert_eq!(1.0, report.accounts[0].total());
+    }
+    #[test]
+    fn test_screening() {
+        let mut engine =
+            Engine::new().with_screening(Screening::new(ScreeningMode::Block, [2]).with_lock(true));
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\n";
+        engine.process_reader(input.as_bytes()).unwrap();
+        let report = engine.snapshot();
+        assert_eq!(Some(&1), report.stats.rejected.get("client_blocked"));
+        assert_eq!(1, report.accounts.len());
+        // accounts of clients added to the list are locked on their next row or when the list is reloaded
+        let locks = Arc::default();
+        let mut engine = Engine::new().with_observer(Locks(Arc::clone(&locks)));
+        engine.process_reader(input.as_bytes()).unwrap();
+        engine.screening = Screening::new(ScreeningMode::Block, [2]).with_lock(true);
+        let row = "type,client,tx,amount\ndeposit,2,3,1.0\n";
+        engine.process_reader(row.as_bytes()).unwrap();
+        engine.screening = Screening::new(ScreeningMode::Allow, [2]).with_lock(true);
+        engine.lock_screened();
+        assert_eq!(
+            vec!["locked:2:client_blocked", "locked:1:client_blocked"],
+            *locks.lock().unwrap()
+        );
+        let report = engine.finish().unwrap();
+        assert_eq!(
+            vec![(1, true), (2, true)],
+            report
+                .accounts
+                .iter()
+                .map(|a| (a.client_id(), a.is_locked()))
+                .collect::<Vec<_>>()
+        );
+    }
+    #[test]
+    fn test_fraud_observed() {
+        let locks = Arc::default();
+        let fraud = Fraud::new(vec![
+            FraudRule::Withdrawals {
+                count: 2,
+                window: 5,
+                action: Action::Hold,
+            },
+            FraudRule::Disputes {
+                count: 1,
+                window: 5,
+                action: Action::Lock,
+            },
+        ]);
+        let mut engine = Engine::new()
+            .with_fraud(fraud)
+            .with_observer(Locks(Arc::clone(&locks)));
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,1.0\nwithdrawal,1,3,1.0\ndispute,1,2,\n";
+        engine.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            vec!["held:1:velocity_withdrawals", "locked:1:velocity_disputes"],
+            *locks.lock().unwrap()
+        );
+    }
+    #[test]
+    fn test_until() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,3.0\nwithdrawal,1,3,1.0\n";
+        let mut engine = Engine::new().with_until(Until::Tx(2));
+        engine.process_reader(input.as_bytes()).unwrap();
+        assert!(engine.is_stopped());
+        engine.process_reader(input.as_bytes()).unwrap();
+        let report = engine.finish().unwrap();
+        assert_eq!(2, report.stats.rows_read);
+        assert_eq!(5.0, report.accounts[0].total());
+
+        let mut engine = Engine::new()
+            .with_input_format(InputFormat::Jsonl)
+            .with_until(Until::Line(1));
+        engine
+            .process_reader(
+                "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":2.0}\nx\n".as_bytes(),
+            )
+            .unwrap();
+        let report = engine.finish().unwrap();
+        assert_eq!(1, report.stats.rows_read);
+        assert!(report.stats.rejected.is_empty());
+    }
+    #[test]
+    fn test_account_at() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,3.0\nwithdrawal,1,3,1.0\ndeposit,1,4,5.0\nwithdrawal,1,5,9.0\n";
+        let mut engine = Engine::new().with_snapshots(2);
+        engine.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(5, engine.seq());
+        let total = |seq| engine.account_at(1, seq).map(|a| a.total());
+        assert_eq!(None, total(0));
+        assert_eq!(Some(2.0), total(1));
+        assert_eq!(Some(2.0), total(2));
+        assert_eq!(Some(1.0), total(3));
+        assert_eq!(Some(6.0), total(4));
+        assert_eq!(Some(6.0), total(5));
+        assert!(engine.account_at(3, 5).is_none());
+    }
+}
@@ -2,12 +2,38 @@ use std::fmt::Display;
 
 use crate::{account::AccountError, transaction::TransactionError};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub enum Error {
     Account(AccountError),
     Transaction(TransactionError),
     ArgsParse,
-    Other(String),
+    Io(std::io::Error),
+    Parse(csv::Error),
+    Json(serde_json::Error),
+    /// The row fails a validation rule, holds the code of the rule
+    Rule(&'static str),
+    /// The client is screened out by the blocklist or allowlist
+    Blocked,
+    /// The state file has an unsupported format version
+    StateVersion(u32),
+    /// A configuration file has an invalid value, holds the description
+    Config(String),
+}
+impl Error {
+    /// Stable code of the error used in reports and summaries
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Account(err) => err.code(),
+            Self::Transaction(err) => err.code(),
+            Self::ArgsParse => "args",
+            Self::Io(_) => "io",
+            Self::Parse(_) | Self::Json(_) => "parse",
+            Self::Rule(code) => code,
+            Self::Blocked => "client_blocked",
+            Self::StateVersion(_) => "state_version",
+            Self::Config(_) => "config",
+        }
+    }
 }
 
 impl Display for Error {
@@ -23,9 +49,165 @@ impl Display for Error {
             Self::ArgsParse => {
                 write!(f, "The application expects only one argument, that should be an input csv file with transactions")
             }
-            Self::Other(e) => {
-                write!(f, "{}", e)
+            Self::Io(e) => {
+                write!(f, "I/O error: {}", e)
+            }
+            Self::Parse(e) => {
+                write!(f, "Parse error: {}", e)
+            }
+            Self::Json(e) => {
+                write!(f, "Parse error: {}", e)
+            }
+            Self::Rule(code) => {
+                write!(f, "Row fails validation rule {}", code)
+            }
+            Self::Blocked => {
+                write!(f, "Client is not allowed to transact")
+            }
+            Self::StateVersion(version) => {
+                write!(f, "Unsupported state file version {}", version)
+            }
+            Self::Config(msg) => {
+                write!(f, "Invalid configuration: {}", msg)
+            }
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Account(err) => Some(err),
+            Self::Transaction(err) => Some(err),
+            Self::ArgsParse => None,
+            Self::Io(e) => Some(e),
+            Self::Parse(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Rule(_) | Self::Blocked | Self::StateVersion(_) | Self::Config(_) => None,
+        }
+    }
+}
+/// I/O, CSV and JSON errors do not implement `PartialEq`, so they are compared by kind and message
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Account(a), Self::Account(b)) => a == b,
+            (Self::Transaction(a), Self::Transaction(b)) => a == b,
+            (Self::ArgsParse, Self::ArgsParse) => true,
+            (Self::Io(a), Self::Io(b)) => a.kind() == b.kind(),
+            (Self::Parse(a), Self::Parse(b)) => a.to_string() == b.to_string(),
+            (Self::Json(a), Self::Json(b)) => a.to_string() == b.to_string(),
+            (Self::Rule(a), Self::Rule(b)) => a == b,
+            (Self::Blocked, Self::Blocked) => true,
+            (Self::StateVersion(a), Self::StateVersion(b)) => a == b,
+            (Self::Config(a), Self::Config(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<csv::Error> for Error {
+    fn from(value: csv::Error) -> Self {
+        if value.is_io_error() {
+            match value.into_kind() {
+                csv::ErrorKind::Io(e) => Self::Io(e),
+                _ => unreachable!("csv error is checked to be an I/O error"),
             }
+        } else {
+            Self::Parse(value)
+        }
+    }
+}
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        if value.is_io() {
+            Self::Io(value.into())
+        } else {
+            Self::Json(value)
         }
     }
 }
+
+/// Error of an input row with the position and ids it refers to
+#[derive(Debug, PartialEq)]
+pub struct RowError {
+    pub line: u64,
+    pub client: Option<u16>,
+    pub tx: Option<u32>,
+    pub error: Error,
+}
+impl RowError {
+    pub fn code(&self) -> &'static str {
+        self.error.code()
+    }
+}
+impl Display for RowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(client) = self.client {
+            write!(f, ", client {}", client)?;
+        }
+        if let Some(tx) = self.tx {
+            write!(f, ", tx {}", tx)?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+impl std::error::Error for RowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::{Error, RowError};
+    use crate::{account::AccountError, transaction::TransactionError};
+
+    #[test]
+    fn test_codes() {
+        assert_eq!("account_locked", Error::from(AccountError::Locked).code());
+        assert_eq!(
+            "tx_not_found",
+            Error::from(TransactionError::NotFound).code()
+        );
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        assert_eq!("io", Error::from(io).code());
+    }
+    #[test]
+    fn test_row_error() {
+        let err = RowError {
+            line: 3,
+            client: Some(1),
+            tx: Some(5),
+            error: AccountError::Withdraw.into(),
+        };
+        assert_eq!("account_withdraw", err.code());
+        assert_eq!(
+            "line 3, client 1, tx 5: AccountError: Account has not enough money available to withdraw",
+            err.to_string()
+        );
+        let source = err.source().unwrap();
+        assert_eq!(
+            "Account has not enough money available to withdraw",
+            source.source().unwrap().to_string()
+        );
+    }
+    #[test]
+    fn test_csv_error() {
+        let mut rdr = csv::Reader::from_reader("type,client,tx,amount\nfoo,1,1,1.0\n".as_bytes());
+        let err: Error = rdr
+            .deserialize::<crate::transaction::Transaction>()
+            .next()
+            .unwrap()
+            .unwrap_err()
+            .into();
+        assert_eq!("parse", err.code());
+        assert!(err.source().is_some());
+    }
+}
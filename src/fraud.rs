@@ -0,0 +1,348 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    error::Error,
+    transaction::{Transaction, TransactionKind},
+};
+
+/// What happens to the account when a fraud rule is triggered
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// The alert is only reported
+    #[default]
+    Flag,
+    /// Further withdrawals of the account are rejected with `account_on_hold`
+    Hold,
+    /// The account is locked
+    Lock,
+}
+
+/// Rule evaluated on applied rows of a client. Windows are counted in applied rows of the same client.
+/// Written in a config file as e.g. `{"rule": "withdrawals", "count": 3, "window": 5, "action": "hold"}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum FraudRule {
+    /// `count` withdrawals within the last `window` rows
+    Withdrawals {
+        count: usize,
+        window: usize,
+        #[serde(default)]
+        action: Action,
+    },
+    /// A withdrawal of more than `percent` of the available funds in its currency before it
+    WithdrawalShare {
+        percent: f32,
+        #[serde(default)]
+        action: Action,
+    },
+    /// `count` disputes within the last `window` rows
+    Disputes {
+        count: usize,
+        window: usize,
+        #[serde(default)]
+        action: Action,
+    },
+    /// A withdrawal of at least `percent` of a deposit in the same currency made within the last `window` rows
+    DepositWithdraw {
+        window: usize,
+        #[serde(default = "default_percent")]
+        percent: f32,
+        #[serde(default)]
+        action: Action,
+    },
+}
+fn default_percent() -> f32 {
+    90.0
+}
+impl FraudRule {
+    /// Stable name of the rule used in alerts and summaries
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Withdrawals { .. } => "velocity_withdrawals",
+            Self::WithdrawalShare { .. } => "withdrawal_share",
+            Self::Disputes { .. } => "velocity_disputes",
+            Self::DepositWithdraw { .. } => "deposit_withdraw",
+        }
+    }
+    pub fn action(&self) -> Action {
+        match self {
+            Self::Withdrawals { action, .. }
+            | Self::WithdrawalShare { action, .. }
+            | Self::Disputes { action, .. }
+            | Self::DepositWithdraw { action, .. } => *action,
+        }
+    }
+    fn window(&self) -> usize {
+        match self {
+            Self::Withdrawals { window, .. }
+            | Self::Disputes { window, .. }
+            | Self::DepositWithdraw { window, .. } => *window,
+            Self::WithdrawalShare { .. } => 1,
+        }
+    }
+    /// `recent` ends with the row being checked, `available` is in the currency of the row before it.
+    /// Amounts are only compared with rows in the currency of the checked row
+    fn is_triggered(
+        &self,
+        recent: &VecDeque<(TransactionKind, Currency, f32)>,
+        available: f32,
+    ) -> bool {
+        let Some(&(kind, currency, amount)) = recent.back() else {
+            return false;
+        };
+        let last = |window: usize| recent.iter().rev().take(window);
+        match self {
+            Self::Withdrawals { count, window, .. } => {
+                kind == TransactionKind::Withdrawal
+                    && last(*window)
+                        .filter(|(k, _, _)| *k == TransactionKind::Withdrawal)
+                        .count()
+                        >= *count
+            }
+            Self::WithdrawalShare { percent, .. } => {
+                kind == TransactionKind::Withdrawal && amount > available * percent / 100.0
+            }
+            Self::Disputes { count, window, .. } => {
+                kind == TransactionKind::Dispute
+                    && last(*window)
+                        .filter(|(k, _, _)| *k == TransactionKind::Dispute)
+                        .count()
+                        >= *count
+            }
+            Self::DepositWithdraw {
+                window, percent, ..
+            } => {
+                kind == TransactionKind::Withdrawal
+                    && last(*window).skip(1).any(|(k, c, deposit)| {
+                        *k == TransactionKind::Deposit
+                            && *c == currency
+                            && amount >= deposit * percent / 100.0
+                    })
+            }
+        }
+    }
+}
+
+/// Triggered fraud rule
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub line: u64,
+    pub client: u16,
+    pub tx: u32,
+    pub rule: &'static str,
+    pub action: Action,
+}
+
+/// Fraud rules with the recent applied rows of every client
+#[derive(Debug, Default)]
+pub struct Fraud {
+    rules: Vec<FraudRule>,
+    /// Kind, currency and amount of the last applied rows, the amount of a dispute is 0
+    clients: HashMap<u16, VecDeque<(TransactionKind, Currency, f32)>>,
+    depth: usize,
+}
+impl Fraud {
+    pub fn new(rules: Vec<FraudRule>) -> Self {
+        let depth = rules
+            .iter()
+            .map(FraudRule::window)
+            .max()
+            .unwrap_or_default();
+        Fraud {
+            rules,
+            clients: HashMap::new(),
+            depth,
+        }
+    }
+    /// Reads rules of a JSON config: `{"rules": [{"rule": "withdrawal_share", "percent": 80, "action": "lock"}]}`
+    pub fn from_json(rdr: impl Read) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct Config {
+            rules: Vec<FraudRule>,
+        }
+        let config: Config = serde_json::from_reader(rdr)?;
+        Ok(Self::new(config.rules))
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    /// Records an applied row and returns the rules it triggers.
+    /// `before` is the account of the client before the row
+    pub fn check(&mut self, line: u64, tx: &Transaction, before: Option<&Account>) -> Vec<Alert> {
+        if self.rules.is_empty() {
+            return vec![];
+        }
+        let recent = self.clients.entry(tx.client_id).or_default();
+        recent.push_back((tx.kind, tx.currency, tx.amount.unwrap_or_default()));
+        if recent.len() > self.depth {
+            recent.pop_front();
+        }
+        let available = before
+            .map(|a| a.funds(tx.currency).available)
+            .unwrap_or_default();
+        self.rules
+            .iter()
+            .filter(|rule| rule.is_triggered(recent, available))
+            .map(|rule| Alert {
+                line,
+                client: tx.client_id,
+                tx: tx.tx,
+                rule: rule.name(),
+                action: rule.action(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Fraud, FraudRule};
+    use crate::{
+        account::Account,
+        currency::Currency,
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    fn tx(kind: TransactionKind, tx: u32, amount: Option<f32>) -> Transaction {
+        Transaction {
+            kind,
+            client_id: 1,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        }
+    }
+
+    /// Names of the rules triggered by every row
+    fn run(
+        fraud: &mut Fraud,
+        txs: &[Transaction],
+        before: Option<&Account>,
+    ) -> Vec<Vec<&'static str>> {
+        txs.iter()
+            .enumerate()
+            .map(|(i, t)| {
+                fraud
+                    .check(i as u64 + 1, t, before)
+                    .iter()
+                    .map(|a| a.rule)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        use TransactionKind::*;
+        let config = r#"{"rules": [
+            {"rule": "withdrawals", "count": 2, "window": 3, "action": "hold"},
+            {"rule": "disputes", "count": 2, "window": 10},
+            {"rule": "deposit_withdraw", "window": 2, "action": "lock"}
+        ]}"#;
+        let mut fraud = Fraud::from_json(config.as_bytes()).unwrap();
+        let txs = [
+            tx(Withdrawal, 1, Some(1.0)),
+            tx(Deposit, 2, Some(10.0)),
+            tx(Deposit, 3, Some(1.0)),
+            tx(Withdrawal, 4, Some(9.0)),
+            tx(Deposit, 5, Some(1.0)),
+            tx(Deposit, 6, Some(1.0)),
+            tx(Withdrawal, 7, Some(1.0)),
+            tx(Dispute, 2, None),
+            tx(Dispute, 3, None),
+        ];
+        assert_eq!(
+            vec![
+                vec![],
+                vec![],
+                vec![],
+                vec!["deposit_withdraw"],
+                vec![],
+                vec![],
+                vec!["deposit_withdraw"],
+                vec![],
+                vec!["velocity_disputes"],
+            ],
+            run(&mut fraud, &txs, None)
+        );
+        let mut fraud = Fraud::new(vec![FraudRule::Withdrawals {
+            count: 2,
+            window: 3,
+            action: Action::Hold,
+        }]);
+        let txs = [
+            tx(Withdrawal, 1, Some(1.0)),
+            tx(Deposit, 2, Some(1.0)),
+            tx(Withdrawal, 3, Some(1.0)),
+        ];
+        assert_eq!(
+            vec![vec![], vec![], vec!["velocity_withdrawals"]],
+            run(&mut fraud, &txs, None)
+        );
+    }
+    #[test]
+    fn test_withdrawal_share() {
+        let mut fraud = Fraud::new(vec![FraudRule::WithdrawalShare {
+            percent: 50.0,
+            action: Action::Lock,
+        }]);
+        let mut acc = Account::new(1);
+        acc.deposit(Currency::NONE, 10.0).unwrap();
+        let alerts = fraud.check(
+            1,
+            &tx(TransactionKind::Withdrawal, 1, Some(6.0)),
+            Some(&acc),
+        );
+        assert_eq!(Action::Lock, alerts[0].action);
+        let alerts = fraud.check(
+            2,
+            &tx(TransactionKind::Withdrawal, 2, Some(5.0)),
+            Some(&acc),
+        );
+        assert!(alerts.is_empty());
+    }
+    #[test]
+    fn test_currencies() {
+        use TransactionKind::*;
+        let usd: Currency = "USD".parse().unwrap();
+        let eur: Currency = "EUR".parse().unwrap();
+        let in_currency = |t: Transaction, currency| Transaction { currency, ..t };
+        let mut fraud = Fraud::new(vec![FraudRule::DepositWithdraw {
+            window: 3,
+            percent: 90.0,
+            action: Action::Lock,
+        }]);
+        let txs = [
+            in_currency(tx(Deposit, 1, Some(10.0)), usd),
+            in_currency(tx(Withdrawal, 2, Some(10.0)), eur),
+            in_currency(tx(Withdrawal, 3, Some(10.0)), usd),
+        ];
+        assert_eq!(
+            vec![vec![], vec![], vec!["deposit_withdraw"]],
+            run(&mut fraud, &txs, None)
+        );
+
+        let mut fraud = Fraud::new(vec![FraudRule::WithdrawalShare {
+            percent: 50.0,
+            action: Action::Flag,
+        }]);
+        let mut acc = Account::new(1);
+        acc.deposit(usd, 10.0).unwrap();
+        acc.deposit(eur, 100.0).unwrap();
+        let txs = [
+            in_currency(tx(Withdrawal, 4, Some(6.0)), eur),
+            in_currency(tx(Withdrawal, 5, Some(6.0)), usd),
+        ];
+        assert_eq!(
+            vec![vec![], vec!["withdrawal_share"]],
+            run(&mut fraud, &txs, Some(&acc))
+        );
+    }
+}
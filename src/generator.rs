@@ -0,0 +1,795 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use fastrand::Rng;
+use fastrand_contrib::RngExt;
+
+use crate::currency::Currency;
+use crate::transaction::{Transaction, TransactionKind, TransactionState};
+
+/// Relative weights of transaction kinds in the random part of the output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Weights {
+    pub deposit: u32,
+    pub withdrawal: u32,
+    pub dispute: u32,
+    pub resolve: u32,
+    pub chargeback: u32,
+}
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            deposit: 1,
+            withdrawal: 1,
+            dispute: 1,
+            resolve: 1,
+            chargeback: 1,
+        }
+    }
+}
+impl Weights {
+    fn pick(&self, rng: &mut Rng) -> TransactionKind {
+        let kinds = [
+            (TransactionKind::Deposit, self.deposit),
+            (TransactionKind::Withdrawal, self.withdrawal),
+            (TransactionKind::Dispute, self.dispute),
+            (TransactionKind::Resolve, self.resolve),
+            (TransactionKind::Chargeback, self.chargeback),
+        ];
+        let total: u32 = kinds.iter().map(|(_, w)| w).sum();
+        let mut n = rng.u32(..total.max(1));
+        for (kind, w) in kinds {
+            if n < w {
+                return kind;
+            }
+            n -= w;
+        }
+        TransactionKind::Deposit
+    }
+}
+/// Parses weights like `deposit=5,withdrawal=3,dispute=1`. Kinds that are not listed get `0`
+impl FromStr for Weights {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Weights {
+            deposit: 0,
+            withdrawal: 0,
+            dispute: 0,
+            resolve: 0,
+            chargeback: 0,
+        };
+        for part in s.split(',') {
+            let (kind, w) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected `kind=weight`, got `{}`", part))?;
+            let w: u32 = w
+                .parse()
+                .map_err(|_| format!("invalid weight `{}` for `{}`", w, kind))?;
+            match kind {
+                "deposit" => weights.deposit = w,
+                "withdrawal" => weights.withdrawal = w,
+                "dispute" => weights.dispute = w,
+                "resolve" => weights.resolve = w,
+                "chargeback" => weights.chargeback = w,
+                _ => return Err(format!("unknown transaction kind `{}`", kind)),
+            }
+        }
+        // `pick` draws from the sum of the weights, so it has to fit into `u32`
+        let total = [
+            weights.withdrawal,
+            weights.dispute,
+            weights.resolve,
+            weights.chargeback,
+        ]
+        .into_iter()
+        .try_fold(weights.deposit, u32::checked_add)
+        .ok_or_else(|| format!("weights sum above {}", u32::MAX))?;
+        if total == 0 {
+            return Err("at least one weight has to be positive".to_string());
+        }
+        Ok(weights)
+    }
+}
+
+/// Distribution of deposit and withdrawal amounts. Amounts are rounded to cents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Amount {
+    Fixed(f32),
+    Uniform(f32, f32),
+    Normal(f32, f32),
+}
+/// Smallest amount after rounding to cents
+const MIN_AMOUNT: f32 = 0.01;
+
+impl Amount {
+    fn sample(&self, rng: &mut Rng) -> f32 {
+        let amount = match *self {
+            Self::Fixed(x) => x,
+            Self::Uniform(min, max) => rng.f32_range(min..max),
+            Self::Normal(mu, sigma) => rng.f32_normal(mu, sigma).max(MIN_AMOUNT),
+        };
+        (amount * 100.0).round() / 100.0
+    }
+}
+/// Parses `fixed:<x>`, `uniform:<min>:<max>` or `normal:<mean>:<sigma>`, parameters have to be
+/// finite and not negative. Amounts are rounded to cents and the engine rejects zero amounts,
+/// so `x` and `min` have to be at least `0.01`
+impl FromStr for Amount {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let num = |i: usize| -> Result<f32, String> {
+            let x: f32 = parts
+                .get(i)
+                .ok_or_else(|| format!("missing parameter in `{}`", s))?
+                .parse()
+                .map_err(|_| format!("invalid number in `{}`", s))?;
+            match x.is_finite() && x >= 0.0 {
+                true => Ok(x),
+                false => Err(format!("negative or non-finite number in `{}`", s)),
+            }
+        };
+        let amount = match parts[0] {
+            "fixed" => Self::Fixed(num(1)?),
+            "uniform" => Self::Uniform(num(1)?, num(2)?),
+            "normal" => Self::Normal(num(1)?, num(2)?),
+            _ => return Err(format!("unknown distribution `{}`", parts[0])),
+        };
+        match amount {
+            Self::Fixed(x) | Self::Uniform(x, _) if x < MIN_AMOUNT => Err(format!(
+                "amounts below {} in `{}` would be rejected",
+                MIN_AMOUNT, s
+            )),
+            Self::Uniform(min, max) if min >= max => Err(format!("empty range in `{}`", s)),
+            _ => Ok(amount),
+        }
+    }
+}
+
+/// Named presets of the generator configuration
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum Scenario {
+    /// All kinds are equally likely
+    #[default]
+    Default,
+    /// Most rows open disputes
+    HeavyDisputes,
+    /// Disputes are mostly followed by chargebacks, so many accounts get locked
+    ChargebackStorm,
+    /// Withdrawals exceed the available money
+    Overdraft,
+    /// Every account is locked right after the initial deposits and keeps receiving traffic
+    LockedTraffic,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub seed: u64,
+    pub num_clients: u16,
+    /// Total number of rows including the initial deposits
+    pub num_lines: usize,
+    pub weights: Weights,
+    pub deposit_amount: Amount,
+    pub withdrawal_amount: Amount,
+    /// Number of deposits every client starts with
+    pub initial_deposits: usize,
+    pub initial_amount: f32,
+    /// Dispute and charge back the first deposit of every client before the random rows
+    pub lock_clients: bool,
+    /// Share of random rows replaced by malformed or adversarial ones, from `0.0` to `1.0`
+    pub malformed_rate: f32,
+    /// Maximum number of transaction ids per client that later rows can refer to
+    pub history_limit: usize,
+    /// `(index, count)` of the part of clients, rows and transaction ids this generator produces.
+    /// Partitions of the same config have disjoint clients and ids, so they can be generated in parallel
+    pub partition: (usize, usize),
+}
+impl Config {
+    pub fn new(num_clients: u16, num_lines: usize) -> Self {
+        Config {
+            seed: 0x42,
+            num_clients,
+            num_lines,
+            weights: Weights::default(),
+            deposit_amount: Amount::Uniform(1.5, 3.0),
+            withdrawal_amount: Amount::Uniform(1.5, 3.0),
+            initial_deposits: 4,
+            initial_amount: 100.0,
+            lock_clients: false,
+            malformed_rate: 0.0,
+            history_limit: 1024,
+            partition: (0, 1),
+        }
+    }
+    pub fn with_seed(self, seed: u64) -> Self {
+        Config { seed, ..self }
+    }
+    /// Splits the config into `count` partitions, every one has its own seed.
+    /// There are at most as many partitions as clients, a partition without clients would emit no rows
+    pub fn partitions(&self, count: usize) -> Vec<Config> {
+        let count = count.clamp(1, self.num_clients.max(1) as usize);
+        (0..count)
+            .map(|index| Config {
+                seed: self.seed.wrapping_add(index as u64),
+                partition: (index, count),
+                ..self.clone()
+            })
+            .collect()
+    }
+    pub fn with_scenario(self, scenario: Scenario) -> Self {
+        let weights = |deposit, withdrawal, dispute, resolve, chargeback| Weights {
+            deposit,
+            withdrawal,
+            dispute,
+            resolve,
+            chargeback,
+        };
+        match scenario {
+            Scenario::Default => self,
+            Scenario::HeavyDisputes => Config {
+                weights: weights(3, 2, 6, 3, 1),
+                ..self
+            },
+            Scenario::ChargebackStorm => Config {
+                weights: weights(3, 1, 4, 1, 6),
+                ..self
+            },
+            Scenario::Overdraft => Config {
+                weights: weights(1, 6, 1, 1, 1),
+                withdrawal_amount: Amount::Uniform(50.0, 250.0),
+                ..self
+            },
+            Scenario::LockedTraffic => Config {
+                lock_clients: true,
+                ..self
+            },
+        }
+    }
+}
+
+/// Kinds of injected malformed or adversarial rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Malformation {
+    NegativeAmount,
+    ZeroAmount,
+    DisputeAmount,
+    MissingField,
+    ExtraWhitespace,
+    UnknownKind,
+    DuplicateTx,
+    OtherClient,
+    Overflow,
+}
+impl Malformation {
+    const ALL: [Malformation; 9] = [
+        Self::NegativeAmount,
+        Self::ZeroAmount,
+        Self::DisputeAmount,
+        Self::MissingField,
+        Self::ExtraWhitespace,
+        Self::UnknownKind,
+        Self::DuplicateTx,
+        Self::OtherClient,
+        Self::Overflow,
+    ];
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NegativeAmount => "negative_amount",
+            Self::ZeroAmount => "zero_amount",
+            Self::DisputeAmount => "dispute_amount",
+            Self::MissingField => "missing_field",
+            Self::ExtraWhitespace => "extra_whitespace",
+            Self::UnknownKind => "unknown_kind",
+            Self::DuplicateTx => "duplicate_tx",
+            Self::OtherClient => "other_client",
+            Self::Overflow => "overflow",
+        }
+    }
+}
+
+/// Generated input row
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub data: RowData,
+    /// Set for injected malformed or adversarial rows
+    pub tag: Option<Malformation>,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowData {
+    Tx(Transaction),
+    /// `type,client,tx,amount` fields of a row that does not parse into a transaction
+    Raw(Vec<String>),
+}
+/// Output file formats
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Csv,
+    /// One JSON object per line with `type`, `client`, `tx` and `amount` keys
+    Jsonl,
+}
+impl Format {
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            Self::Csv => Some("type,client,tx,amount"),
+            Self::Jsonl => None,
+        }
+    }
+}
+impl Row {
+    pub fn transaction(&self) -> Option<&Transaction> {
+        match &self.data {
+            RowData::Tx(tx) => Some(tx),
+            RowData::Raw(_) => None,
+        }
+    }
+    /// Appends the row to `out` in the given format followed by a line break
+    pub fn write(&self, format: Format, out: &mut String) {
+        match format {
+            Format::Csv => self.write_csv(out),
+            Format::Jsonl => self.write_json(out),
+        }
+        out.push('\n');
+    }
+    fn write_csv(&self, out: &mut String) {
+        let _ = match &self.data {
+            RowData::Tx(tx) => match tx.amount {
+                Some(amount) => write!(out, "{},{},{},{}", tx.kind, tx.client_id, tx.tx, amount),
+                None => write!(out, "{},{},{},", tx.kind, tx.client_id, tx.tx),
+            },
+            RowData::Raw(fields) => write!(out, "{}", fields.join(",")),
+        };
+    }
+    fn write_json(&self, out: &mut String) {
+        let _ = match &self.data {
+            RowData::Tx(tx) => match tx.amount {
+                Some(amount) => write!(
+                    out,
+                    r#"{{"type":"{}","client":{},"tx":{},"amount":{}}}"#,
+                    tx.kind, tx.client_id, tx.tx, amount
+                ),
+                None => write!(
+                    out,
+                    r#"{{"type":"{}","client":{},"tx":{}}}"#,
+                    tx.kind, tx.client_id, tx.tx
+                ),
+            },
+            RowData::Raw(fields) => {
+                // numbers are written as is, anything else as a string, empty fields are left out
+                let values: Vec<String> = ["type", "client", "tx", "amount"]
+                    .iter()
+                    .zip(fields)
+                    .filter(|(_, v)| !v.is_empty())
+                    .map(|(k, v)| match v.parse::<f64>() {
+                        Ok(_) => format!(r#""{}":{}"#, k, v),
+                        Err(_) => format!(r#""{}":"{}""#, k, v),
+                    })
+                    .collect();
+                write!(out, "{{{}}}", values.join(","))
+            }
+        };
+    }
+    /// Formats the row as a line of the `type,client,tx,amount` file without a line break
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        self.write_csv(&mut out);
+        out
+    }
+}
+impl From<Transaction> for Row {
+    fn from(tx: Transaction) -> Self {
+        Row {
+            data: RowData::Tx(tx),
+            tag: None,
+        }
+    }
+}
+
+/// Deposits and withdrawals of a client that can be referenced by later rows.
+/// Both lists are bounded by `Config::history_limit`, so memory does not grow with the number of rows
+#[derive(Debug, Default)]
+struct ClientHistory {
+    txs: Vec<u32>,
+    disputed: Vec<u32>,
+    disputed_set: HashSet<u32>,
+}
+impl ClientHistory {
+    fn random_tx(&self, rng: &mut Rng) -> Option<u32> {
+        match self.txs.len() {
+            0 => None,
+            n => Some(self.txs[rng.usize(..n)]),
+        }
+    }
+    fn take_disputed(&mut self, rng: &mut Rng) -> Option<u32> {
+        match self.disputed.len() {
+            0 => None,
+            n => {
+                let tx = self.disputed.swap_remove(rng.usize(..n));
+                self.disputed_set.remove(&tx);
+                Some(tx)
+            }
+        }
+    }
+}
+
+/// Iterator over generated rows. The same `Config` always produces the same rows
+pub struct Generator {
+    config: Config,
+    rng: Rng,
+    next_tx: u32,
+    emitted: usize,
+    /// Number of rows of the partition
+    num_lines: usize,
+    /// Clients of the partition are `first_client + i * step`
+    first_client: usize,
+    num_clients: usize,
+    step: usize,
+    /// History indexed by client id
+    history: Vec<ClientHistory>,
+    /// Rows generated ahead of the random part, like the initial deposits
+    pending: VecDeque<Row>,
+}
+impl Generator {
+    pub fn new(config: Config) -> Self {
+        let (index, count) = config.partition;
+        let total = config.num_clients as usize;
+        let num_clients = total / count + usize::from(index < total % count);
+        let num_lines = config.num_lines / count + usize::from(index < config.num_lines % count);
+        let mut generator = Generator {
+            rng: Rng::with_seed(config.seed),
+            next_tx: index as u32 + 1,
+            emitted: 0,
+            num_lines,
+            first_client: index + 1,
+            num_clients,
+            step: count,
+            history: (0..=total).map(|_| ClientHistory::default()).collect(),
+            pending: Default::default(),
+            config,
+        };
+        generator.initial_rows();
+        generator
+    }
+    fn clients(&self) -> impl Iterator<Item = u16> {
+        (self.first_client..=self.config.num_clients as usize)
+            .step_by(self.step)
+            .map(|c| c as u16)
+    }
+    fn random_client(&mut self) -> u16 {
+        (self.first_client + self.rng.usize(..self.num_clients) * self.step) as u16
+    }
+    /// Takes the next transaction id of the partition
+    fn take_tx(&mut self) -> u32 {
+        let tx = self.next_tx;
+        self.next_tx += self.step as u32;
+        tx
+    }
+    /// The last taken transaction id, used when a client has nothing to refer to
+    fn last_tx(&self) -> u32 {
+        self.next_tx.saturating_sub(self.step as u32).max(1)
+    }
+    fn initial_rows(&mut self) {
+        let clients: Vec<u16> = self.clients().collect();
+        for &client_id in &clients {
+            for _ in 0..self.config.initial_deposits {
+                let amount = self.config.initial_amount;
+                let tx = self.money(TransactionKind::Deposit, client_id, amount);
+                self.pending.push_back(tx.into());
+            }
+        }
+        if self.config.lock_clients {
+            for &client_id in &clients {
+                let Some(&tx) = self.history[client_id as usize].txs.first() else {
+                    continue;
+                };
+                self.pending
+                    .push_back(reference(TransactionKind::Dispute, client_id, tx).into());
+                self.pending
+                    .push_back(reference(TransactionKind::Chargeback, client_id, tx).into());
+            }
+        }
+    }
+    fn money(&mut self, kind: TransactionKind, client_id: u16, amount: f32) -> Transaction {
+        let tx = self.take_tx();
+        let txs = &mut self.history[client_id as usize].txs;
+        if txs.len() < self.config.history_limit {
+            txs.push(tx);
+        } else {
+            txs[self.rng.usize(..self.config.history_limit)] = tx;
+        }
+        Transaction {
+            kind,
+            client_id,
+            tx,
+            amount: Some(amount),
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        }
+    }
+    fn malformed_row(&mut self) -> Row {
+        let tag = Malformation::ALL[self.rng.usize(..Malformation::ALL.len())];
+        let client_id = self.random_client();
+        let amount = self.config.deposit_amount.sample(&mut self.rng);
+        let tx = |kind, tx, amount| Transaction {
+            kind,
+            client_id,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        };
+        let raw = |kind: &str, client: String, tx: String| {
+            RowData::Raw(vec![kind.to_string(), client, tx, amount.to_string()])
+        };
+        let data = match tag {
+            Malformation::NegativeAmount => {
+                let row = tx(TransactionKind::Withdrawal, self.take_tx(), Some(-amount));
+                RowData::Tx(row)
+            }
+            Malformation::ZeroAmount => {
+                let row = self.money(TransactionKind::Deposit, client_id, 0.0);
+                RowData::Tx(row)
+            }
+            Malformation::DisputeAmount => {
+                let id = self.rng.u32(1..self.next_tx.max(2));
+                RowData::Tx(tx(TransactionKind::Dispute, id, Some(amount)))
+            }
+            Malformation::MissingField => raw("deposit", client_id.to_string(), String::new()),
+            Malformation::ExtraWhitespace => raw(
+                "deposit",
+                format!(" {}", client_id),
+                format!(" {}", self.take_tx()),
+            ),
+            Malformation::UnknownKind => raw(
+                "transfer",
+                client_id.to_string(),
+                self.take_tx().to_string(),
+            ),
+            Malformation::DuplicateTx => {
+                let id = self.rng.u32(1..self.next_tx.max(2));
+                RowData::Tx(tx(TransactionKind::Deposit, id, Some(amount)))
+            }
+            Malformation::OtherClient => {
+                let other = self.random_client();
+                let id = match self.history[other as usize].random_tx(&mut self.rng) {
+                    Some(id) if other != client_id => id,
+                    _ => self.last_tx(),
+                };
+                RowData::Tx(tx(TransactionKind::Dispute, id, None))
+            }
+            Malformation::Overflow => {
+                if self.rng.bool() {
+                    raw(
+                        "deposit",
+                        (1u32 << 16).to_string(),
+                        self.take_tx().to_string(),
+                    )
+                } else {
+                    raw("deposit", client_id.to_string(), (1u64 << 32).to_string())
+                }
+            }
+        };
+        Row {
+            data,
+            tag: Some(tag),
+        }
+    }
+    fn random_row(&mut self) -> Transaction {
+        let client_id = self.random_client();
+        let kind = self.config.weights.pick(&mut self.rng);
+        match kind {
+            TransactionKind::Deposit => {
+                let amount = self.config.deposit_amount.sample(&mut self.rng);
+                self.money(kind, client_id, amount)
+            }
+            TransactionKind::Withdrawal => {
+                let amount = self.config.withdrawal_amount.sample(&mut self.rng);
+                self.money(kind, client_id, amount)
+            }
+            TransactionKind::Dispute => {
+                let last_tx = self.last_tx();
+                let limit = self.config.history_limit;
+                let history = &mut self.history[client_id as usize];
+                let tx = history.random_tx(&mut self.rng).unwrap_or(last_tx);
+                if history.disputed.len() < limit && history.disputed_set.insert(tx) {
+                    history.disputed.push(tx);
+                }
+                reference(kind, client_id, tx)
+            }
+            TransactionKind::Resolve | TransactionKind::Chargeback => {
+                let last_tx = self.last_tx();
+                let history = &mut self.history[client_id as usize];
+                let tx = history
+                    .take_disputed(&mut self.rng)
+                    .or_else(|| history.random_tx(&mut self.rng))
+                    .unwrap_or(last_tx);
+                reference(kind, client_id, tx)
+            }
+        }
+    }
+}
+impl Iterator for Generator {
+    type Item = Row;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.emitted >= self.num_lines {
+            return None;
+        }
+        self.emitted += 1;
+        match self.pending.pop_front() {
+            Some(row) => Some(row),
+            None if self.num_clients == 0 => None,
+            None if self.config.malformed_rate > 0.0
+                && self.rng.f32() < self.config.malformed_rate =>
+            {
+                Some(self.malformed_row())
+            }
+            None => Some(self.random_row().into()),
+        }
+    }
+}
+fn reference(kind: TransactionKind, client_id: u16, tx: u32) -> Transaction {
+    Transaction {
+        kind,
+        client_id,
+        tx,
+        amount: None,
+        currency: Currency::NONE,
+        state: TransactionState::Processing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{Amount, Config, Format, Generator, Malformation, Row, Scenario, Weights};
+    use crate::transaction::{Transaction, TransactionKind};
+
+    fn to_csv(rows: impl Iterator<Item = Row>) -> String {
+        let mut data = "type,client,tx,amount\n".to_string();
+        for row in rows {
+            data.push_str(&row.to_csv());
+            data.push('\n');
+        }
+        data
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let config = Config::new(5, 200).with_seed(7);
+        let a = to_csv(Generator::new(config.clone()));
+        let b = to_csv(Generator::new(config.clone()));
+        let c = to_csv(Generator::new(config.with_seed(8)));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+    #[test]
+    fn test_kinds_roundtrip() {
+        let config = Config::new(3, 100).with_scenario(Scenario::HeavyDisputes);
+        let data = to_csv(Generator::new(config));
+        assert!(data.starts_with("type,client,tx,amount\ndeposit,1,1,100\n"));
+        let mut rdr = csv::Reader::from_reader(data.as_bytes());
+        let txs: Vec<Transaction> = rdr.deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(100, txs.len());
+        assert!(txs.iter().all(|t| t.is_valid()));
+    }
+    #[test]
+    fn test_locked_traffic() {
+        let config = Config::new(2, 20).with_scenario(Scenario::LockedTraffic);
+        let txs: Vec<Transaction> = Generator::new(config)
+            .filter_map(|r| r.transaction().cloned())
+            .collect();
+        assert_eq!(TransactionKind::Dispute, txs[8].kind);
+        assert_eq!(TransactionKind::Chargeback, txs[9].kind);
+        assert_eq!(1, txs[9].tx);
+    }
+    #[test]
+    fn test_parse_config() {
+        assert_eq!(
+            Ok(Weights {
+                deposit: 5,
+                withdrawal: 0,
+                dispute: 1,
+                resolve: 0,
+                chargeback: 0,
+            }),
+            "deposit=5,dispute=1".parse()
+        );
+        assert!("deposit=x".parse::<Weights>().is_err());
+        assert!("deposit=0".parse::<Weights>().is_err());
+        assert!("deposit=4294967295,withdrawal=1"
+            .parse::<Weights>()
+            .is_err());
+        assert!("deposit=4294967294,withdrawal=1".parse::<Weights>().is_ok());
+        assert_eq!(Ok(Amount::Uniform(1.0, 2.0)), "uniform:1:2".parse());
+        assert_eq!(Ok(Amount::Fixed(3.0)), "fixed:3".parse());
+        assert!("uniform:2:1".parse::<Amount>().is_err());
+        assert!("poisson:1".parse::<Amount>().is_err());
+        for invalid in [
+            "fixed:-5",
+            "uniform:-5:-1",
+            "fixed:NaN",
+            "uniform:1:inf",
+            "normal:5:-1",
+            "fixed:0",
+            "uniform:0:1",
+            "fixed:0.001",
+        ] {
+            assert!(invalid.parse::<Amount>().is_err(), "{}", invalid);
+        }
+    }
+    #[test]
+    fn test_malformed() {
+        let config = Config {
+            malformed_rate: 0.5,
+            ..Config::new(3, 400)
+        };
+        let rows: Vec<Row> = Generator::new(config).collect();
+        let tagged: Vec<&Row> = rows.iter().filter(|r| r.tag.is_some()).collect();
+        assert!(tagged.len() > 100);
+        for tag in Malformation::ALL {
+            assert!(tagged.iter().any(|r| r.tag == Some(tag)), "{:?}", tag);
+        }
+        let data = to_csv(rows.iter().cloned());
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(data.as_bytes());
+        for (row, result) in rows.iter().zip(rdr.deserialize::<Transaction>()) {
+            match row.tag {
+                Some(Malformation::MissingField)
+                | Some(Malformation::ExtraWhitespace)
+                | Some(Malformation::UnknownKind)
+                | Some(Malformation::Overflow) => assert!(result.is_err(), "{:?}", row),
+                Some(Malformation::NegativeAmount)
+                | Some(Malformation::ZeroAmount)
+                | Some(Malformation::DisputeAmount) => {
+                    assert!(!result.unwrap().is_valid())
+                }
+                _ => assert_eq!(row.transaction(), Some(&result.unwrap())),
+            }
+        }
+    }
+    #[test]
+    fn test_partitions() {
+        let config = Config {
+            malformed_rate: 0.1,
+            ..Config::new(10, 1000)
+        };
+        let partitions: Vec<Vec<Row>> = config
+            .partitions(3)
+            .into_iter()
+            .map(|c| Generator::new(c).collect())
+            .collect();
+        assert_eq!(1000, partitions.iter().map(|p| p.len()).sum::<usize>());
+        let few = Config::new(2, 100).partitions(4);
+        assert_eq!(2, few.len());
+        assert_eq!(100, few.into_iter().flat_map(Generator::new).count());
+        let mut ids = HashSet::new();
+        for (index, rows) in partitions.iter().enumerate() {
+            let txs = rows.iter().filter(|r| r.tag.is_none());
+            for tx in txs.filter_map(|r| r.transaction()) {
+                assert_eq!(index, (tx.client_id as usize - 1) % 3);
+                if tx.amount.is_some() {
+                    assert!(ids.insert(tx.tx), "{}", tx);
+                }
+            }
+        }
+    }
+    #[test]
+    fn test_jsonl() {
+        let config = Config {
+            malformed_rate: 0.3,
+            ..Config::new(3, 300)
+        };
+        for row in Generator::new(config) {
+            let mut line = String::new();
+            row.write(Format::Jsonl, &mut line);
+            let parsed = serde_json::from_str::<Transaction>(line.trim_end());
+            match row.transaction() {
+                Some(tx) => assert_eq!(tx, &parsed.unwrap()),
+                None => assert!(parsed.is_err(), "{}", line),
+            }
+        }
+    }
+}
@@ -3,3 +3,18 @@ pub mod account;
 pub mod error;
 pub mod db;
 pub mod app;
+pub mod retention;
+pub mod stats;
+pub mod generator;
+pub mod model;
+pub mod engine;
+pub mod observer;
+pub mod rules;
+pub mod fraud;
+pub mod screening;
+pub mod threshold;
+pub mod statement;
+pub mod snapshot;
+pub mod reconcile;
+pub mod state;
+pub mod currency;
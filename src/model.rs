@@ -0,0 +1,439 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
+use std::io::Write;
+
+use crate::account::Account;
+use crate::currency::Currency;
+use crate::db::Db;
+use crate::rules::RuleChain;
+use crate::transaction::{Transaction, TransactionKind};
+
+/// Simple model of the account rules, written independently of `Db`.
+/// It is used to compute the expected output of generated files and as the reference
+/// of differential tests, so it keeps every account and transaction in plain maps
+/// without any retention, policies or optimizations.
+/// Rejected rows are reported with the same codes as `Error::code`
+#[derive(Debug, Default)]
+pub struct Model {
+    accounts: BTreeMap<u16, ModelAccount>,
+    txs: HashMap<u32, ModelTx>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ModelAccount {
+    /// Funds of every currency the client has a deposit or withdrawal in
+    pub funds: BTreeMap<Currency, ModelFunds>,
+    pub locked: bool,
+}
+impl From<&Account> for ModelAccount {
+    fn from(acc: &Account) -> Self {
+        ModelAccount {
+            funds: acc
+                .currencies()
+                .map(|(currency, funds)| {
+                    let funds = ModelFunds {
+                        available: funds.available,
+                        held: funds.held,
+                    };
+                    (currency, funds)
+                })
+                .collect(),
+            locked: acc.is_locked(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ModelFunds {
+    pub available: f32,
+    pub held: f32,
+}
+impl ModelFunds {
+    pub fn total(&self) -> f32 {
+        self.available + self.held
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModelTx {
+    client_id: u16,
+    amount: f32,
+    currency: Currency,
+    is_deposit: bool,
+    disputed: bool,
+    /// Resolved or charged back transactions cannot be disputed again
+    closed: bool,
+}
+
+impl Model {
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), &'static str> {
+        let valid = match tx.kind {
+            TransactionKind::Deposit | TransactionKind::Withdrawal => {
+                tx.amount.is_some_and(|x| x.is_finite() && x > 0.0)
+            }
+            _ => tx.amount.is_none(),
+        };
+        if !valid {
+            return Err("tx_invalid");
+        }
+        match tx.kind {
+            TransactionKind::Deposit | TransactionKind::Withdrawal => {
+                let Some(amount) = tx.amount else {
+                    return Err("tx_unexpected_amount");
+                };
+                if self.txs.contains_key(&tx.tx) {
+                    return Err("tx_duplicate");
+                }
+                let account = self.accounts.entry(tx.client_id).or_default();
+                if account.locked {
+                    return Err("account_locked");
+                }
+                // a currency without rows has no funds
+                let available = account.funds.get(&tx.currency).map_or(0.0, |f| f.available);
+                if tx.kind == TransactionKind::Deposit {
+                    account.funds.entry(tx.currency).or_default().available += amount;
+                } else if available >= amount {
+                    account.funds.entry(tx.currency).or_default().available -= amount;
+                } else {
+                    return Err("account_withdraw");
+                }
+                self.txs.insert(
+                    tx.tx,
+                    ModelTx {
+                        client_id: tx.client_id,
+                        amount,
+                        currency: tx.currency,
+                        is_deposit: tx.kind == TransactionKind::Deposit,
+                        disputed: false,
+                        closed: false,
+                    },
+                );
+                Ok(())
+            }
+            TransactionKind::Dispute | TransactionKind::Resolve | TransactionKind::Chargeback => {
+                let Some(stored) = self.txs.get_mut(&tx.tx) else {
+                    return Err("tx_not_found");
+                };
+                if stored.client_id != tx.client_id {
+                    return Err("tx_client_mismatch");
+                }
+                let account = self.accounts.entry(tx.client_id).or_default();
+                let locked = account.locked;
+                // disputes move the funds of the currency of the referenced transaction
+                let funds = account.funds.entry(stored.currency).or_default();
+                match tx.kind {
+                    TransactionKind::Dispute => {
+                        if stored.disputed || stored.closed {
+                            return Err("tx_dispute");
+                        }
+                        if locked {
+                            return Err("account_locked");
+                        }
+                        if stored.is_deposit {
+                            if funds.available < stored.amount {
+                                return Err("account_dispute");
+                            }
+                            funds.available -= stored.amount;
+                        }
+                        funds.held += stored.amount;
+                        stored.disputed = true;
+                    }
+                    TransactionKind::Resolve => {
+                        if !stored.disputed {
+                            return Err("tx_resolve");
+                        }
+                        if locked {
+                            return Err("account_locked");
+                        }
+                        if funds.held < stored.amount {
+                            return Err("account_resolve");
+                        }
+                        funds.held -= stored.amount;
+                        funds.available += stored.amount;
+                        stored.disputed = false;
+                        stored.closed = true;
+                    }
+                    _ => {
+                        if !stored.disputed {
+                            return Err("tx_chargeback");
+                        }
+                        if locked {
+                            return Err("account_locked");
+                        }
+                        if funds.held < stored.amount {
+                            return Err("account_chargeback");
+                        }
+                        funds.held -= stored.amount;
+                        account.locked = true;
+                        stored.disputed = false;
+                        stored.closed = true;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+    pub fn account(&self, client_id: u16) -> Option<&ModelAccount> {
+        self.accounts.get(&client_id)
+    }
+    pub fn accounts(&self) -> &BTreeMap<u16, ModelAccount> {
+        &self.accounts
+    }
+    /// Writes accounts in the output format of the main binary, ordered by client.
+    /// Funds are written per currency if any client has rows in a currency other than the default one
+    pub fn write_accounts(&self, mut w: impl Write) -> std::io::Result<()> {
+        let currencies = self
+            .accounts
+            .values()
+            .any(|acc| acc.funds.keys().any(|c| !c.is_default()));
+        if !currencies {
+            writeln!(w, "client,available,held,total,locked")?;
+            for (client_id, acc) in &self.accounts {
+                let funds = acc.funds.get(&Currency::NONE).copied().unwrap_or_default();
+                writeln!(
+                    w,
+                    "{},{},{},{},{}",
+                    client_id,
+                    funds.available,
+                    funds.held,
+                    funds.total(),
+                    acc.locked
+                )?;
+            }
+            return Ok(());
+        }
+        writeln!(w, "client,currency,available,held,total,locked")?;
+        for (client_id, acc) in &self.accounts {
+            let mut funds: Vec<_> = acc.funds.iter().map(|(c, f)| (*c, *f)).collect();
+            if funds.is_empty() {
+                funds.push((Currency::NONE, ModelFunds::default()));
+            }
+            for (currency, funds) in funds {
+                writeln!(
+                    w,
+                    "{},{},{},{},{},{}",
+                    client_id,
+                    currency,
+                    funds.available,
+                    funds.held,
+                    funds.total(),
+                    acc.locked
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// First row where `Db` and `Model` disagree, either on the result of the row or on the account after it
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// Index of the row in the stream, or the number of rows if only the final accounts differ
+    pub row: usize,
+    pub tx: Option<Transaction>,
+    pub client_id: u16,
+    pub engine: Result<(), &'static str>,
+    pub model: Result<(), &'static str>,
+    pub engine_account: Option<ModelAccount>,
+    pub model_account: Option<ModelAccount>,
+}
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {}", self.row)?;
+        if let Some(tx) = &self.tx {
+            write!(f, " {}", tx)?;
+        }
+        write!(
+            f,
+            ": engine {:?}, model {:?}; client {} engine {:?}, model {:?}",
+            self.engine, self.model, self.client_id, self.engine_account, self.model_account
+        )
+    }
+}
+
+/// Runs the stream through both `db` and a fresh `Model` and stops at the first divergence.
+/// Every row is checked by the default `RuleChain` before `Db::process`, the same way the engine
+/// of the main binary does it, so the validation of the engine is compared with the model too.
+/// The final accounts are taken out of `db` to compare them all
+pub fn diff(db: &Db, txs: impl IntoIterator<Item = Transaction>) -> Result<Model, Box<Divergence>> {
+    let rules = RuleChain::default();
+    let mut model = Model::default();
+    let mut rows = 0;
+    for (row, tx) in txs.into_iter().enumerate() {
+        rows = row + 1;
+        let engine = rules
+            .check(&tx)
+            .and_then(|_| db.process(tx.clone()))
+            .map_err(|e| e.code());
+        let expected = model.apply(&tx);
+        let engine_account = db.account(tx.client_id).as_ref().map(ModelAccount::from);
+        let model_account = model.account(tx.client_id).cloned();
+        if engine != expected || engine_account != model_account {
+            return Err(Box::new(Divergence {
+                row,
+                client_id: tx.client_id,
+                tx: Some(tx),
+                engine,
+                model: expected,
+                engine_account,
+                model_account,
+            }));
+        }
+    }
+    // a row could also change an account of another client
+    let accounts = db.accounts();
+    let clients = accounts.keys().chain(model.accounts().keys());
+    for client_id in clients.copied().collect::<BTreeSet<u16>>() {
+        let engine_account = accounts.get(&client_id).map(ModelAccount::from);
+        let model_account = model.account(client_id).cloned();
+        if engine_account != model_account {
+            return Err(Box::new(Divergence {
+                row: rows,
+                tx: None,
+                client_id,
+                engine: Ok(()),
+                model: Ok(()),
+                engine_account,
+                model_account,
+            }));
+        }
+    }
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, Divergence, Model, ModelAccount, ModelFunds};
+    use crate::currency::Currency;
+    use crate::db::Db;
+    use crate::transaction::{Transaction, TransactionKind, TransactionState};
+
+    fn tx(kind: TransactionKind, client_id: u16, tx: u32, amount: Option<f32>) -> Transaction {
+        Transaction {
+            kind,
+            client_id,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        }
+    }
+    /// Account with funds only in the default currency
+    fn account(available: f32, held: f32, locked: bool) -> ModelAccount {
+        ModelAccount {
+            funds: [(Currency::NONE, ModelFunds { available, held })].into(),
+            locked,
+        }
+    }
+
+    #[test]
+    fn test_model() {
+        use TransactionKind::*;
+        let mut model = Model::default();
+        assert_eq!(Ok(()), model.apply(&tx(Deposit, 1, 1, Some(4.0))));
+        assert_eq!(Ok(()), model.apply(&tx(Withdrawal, 1, 2, Some(1.0))));
+        assert_eq!(
+            Err("account_withdraw"),
+            model.apply(&tx(Withdrawal, 1, 3, Some(5.0)))
+        );
+        assert_eq!(
+            Err("tx_duplicate"),
+            model.apply(&tx(Deposit, 2, 1, Some(1.0)))
+        );
+        assert_eq!(
+            Err("tx_client_mismatch"),
+            model.apply(&tx(Dispute, 2, 1, None))
+        );
+        assert_eq!(Ok(()), model.apply(&tx(Dispute, 1, 2, None)));
+        assert_eq!(Err("tx_dispute"), model.apply(&tx(Dispute, 1, 2, None)));
+        assert_eq!(Ok(()), model.apply(&tx(Resolve, 1, 2, None)));
+        assert_eq!(Err("tx_resolve"), model.apply(&tx(Resolve, 1, 2, None)));
+        assert_eq!(Err("tx_dispute"), model.apply(&tx(Dispute, 1, 2, None)));
+        assert_eq!(Ok(()), model.apply(&tx(Deposit, 1, 6, Some(1.0))));
+        assert_eq!(Ok(()), model.apply(&tx(Dispute, 1, 6, None)));
+        assert_eq!(Ok(()), model.apply(&tx(Chargeback, 1, 6, None)));
+        assert_eq!(
+            Err("account_locked"),
+            model.apply(&tx(Deposit, 1, 7, Some(1.0)))
+        );
+        assert_eq!(Some(&account(4.0, 0.0, true)), model.account(1));
+        let mut out = vec![];
+        model.write_accounts(&mut out).unwrap();
+        assert_eq!(
+            "client,available,held,total,locked\n1,4,0,4,true\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+    #[test]
+    fn test_currencies() {
+        use TransactionKind::*;
+        let usd: Currency = "USD".parse().unwrap();
+        let in_usd = |t: Transaction| Transaction { currency: usd, ..t };
+        let mut model = Model::default();
+        assert_eq!(Ok(()), model.apply(&tx(Deposit, 1, 1, Some(2.0))));
+        assert_eq!(Ok(()), model.apply(&in_usd(tx(Deposit, 1, 2, Some(5.0)))));
+        assert_eq!(
+            Err("account_withdraw"),
+            model.apply(&tx(Withdrawal, 1, 3, Some(3.0)))
+        );
+        assert_eq!(
+            Ok(()),
+            model.apply(&in_usd(tx(Withdrawal, 1, 4, Some(1.0))))
+        );
+        assert_eq!(Ok(()), model.apply(&tx(Dispute, 1, 4, None)));
+        let acc = model.account(1).unwrap();
+        assert_eq!(
+            Some(&ModelFunds {
+                available: 2.0,
+                held: 0.0
+            }),
+            acc.funds.get(&Currency::NONE)
+        );
+        assert_eq!(
+            Some(&ModelFunds {
+                available: 4.0,
+                held: 1.0
+            }),
+            acc.funds.get(&usd)
+        );
+        let mut out = vec![];
+        model.write_accounts(&mut out).unwrap();
+        assert_eq!(
+            "client,currency,available,held,total,locked\n1,,2,0,2,false\n1,USD,4,1,5,false\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+    #[test]
+    fn test_diff() {
+        use TransactionKind::*;
+        let db = Db::default();
+        db.clean();
+        let txs = vec![
+            tx(Deposit, 1, 1, Some(4.0)),
+            tx(Deposit, 1, 2, Some(-1.0)),
+            tx(Deposit, 1, 4, Some(0.0)),
+            tx(Withdrawal, 1, 5, Some(f32::INFINITY)),
+            tx(Dispute, 1, 1, None),
+            tx(Chargeback, 1, 1, None),
+            tx(Deposit, 1, 3, Some(1.0)),
+        ];
+        let model = diff(&db, txs.clone()).unwrap();
+        assert_eq!(Some(&account(0.0, 0.0, true)), model.account(1));
+        // the engine already has the deposit, so the replay diverges on the first row
+        db.clean();
+        db.process(tx(Deposit, 2, 1, Some(1.0))).unwrap();
+        assert_eq!(
+            Err(Box::new(Divergence {
+                row: 0,
+                tx: Some(txs[0].clone()),
+                client_id: 1,
+                engine: Err("tx_duplicate"),
+                model: Ok(()),
+                engine_account: None,
+                model_account: Some(account(4.0, 0.0, false)),
+            })),
+            diff(&db, txs).map(|_| ())
+        );
+        db.clean();
+    }
+}
@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+
+use crate::{account::Account, error::Error, transaction::Transaction};
+
+/// Account of a client before and after the row is processed.
+/// `None` means the account does not exist
+#[derive(Debug, Clone, Copy)]
+pub struct Change<'a> {
+    pub client_id: u16,
+    /// Processed row of the client, `None` if the account is locked or put on hold outside of a row,
+    /// e.g. by a fraud rule or the screening
+    pub tx: Option<&'a Transaction>,
+    pub before: Option<&'a Account>,
+    pub after: Option<&'a Account>,
+}
+
+/// Callbacks invoked synchronously by `Db::process`.
+/// Every callback has an empty default, so an observer implements only the events it needs.
+/// Observers are `Send`, so a `Db` with observers can be moved to another thread
+pub trait Observer: Send {
+    /// The row is applied
+    fn on_applied(&mut self, _change: &Change) {}
+    /// The row is rejected, the account is left unchanged
+    fn on_rejected(&mut self, _change: &Change, _err: &Error) {}
+    /// A dispute against `disputed` is opened, called after `on_applied`
+    fn on_dispute_opened(&mut self, _change: &Change, _disputed: &Transaction) {}
+    /// The account is locked, either by the row after `on_applied`, or without a row
+    /// by a fraud rule or the screening, see `Account::reason`
+    fn on_account_locked(&mut self, _change: &Change) {}
+    /// The account is put on hold by a fraud rule, `tx` of the change is `None`
+    fn on_account_held(&mut self, _change: &Change) {}
+    /// Called by `Db::flush` after the last row. Callbacks cannot fail, so an observer writing
+    /// an output keeps its first error and returns it here
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Observers registered on `Db`
+#[derive(Default)]
+pub struct Observers(RefCell<Vec<Box<dyn Observer>>>);
+impl Observers {
+    pub fn push(&mut self, observer: Box<dyn Observer>) {
+        self.0.get_mut().push(observer);
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+    /// Calls `f` for every observer in the order they are registered
+    pub fn notify(&self, mut f: impl FnMut(&mut dyn Observer)) {
+        for observer in self.0.borrow_mut().iter_mut() {
+            f(observer.as_mut());
+        }
+    }
+    /// Flushes every observer, returns the first error
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut ret = Ok(());
+        self.notify(|o| {
+            let flushed = o.flush();
+            if ret.is_ok() {
+                ret = flushed;
+            }
+        });
+        ret
+    }
+}
+impl std::fmt::Debug for Observers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Observers({})", self.0.borrow().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{Change, Observer};
+    use crate::{
+        account::Account,
+        currency::Currency,
+        db::Db,
+        error::Error,
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    /// Records events as `event:tx:before>after` with `available/held` of the account
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+    impl Recorder {
+        fn record(&self, event: &str, change: &Change) {
+            let state = |acc: Option<&Account>| {
+                acc.map(|a| format!("{}/{}", a.available(), a.held()))
+                    .unwrap_or_default()
+            };
+            self.0.lock().unwrap().push(format!(
+                "{}:{}:{}>{}",
+                event,
+                change.tx.map_or("-".to_string(), |tx| tx.tx.to_string()),
+                state(change.before),
+                state(change.after)
+            ));
+        }
+    }
+    impl Observer for Recorder {
+        fn on_applied(&mut self, change: &Change) {
+            self.record("applied", change);
+        }
+        fn on_rejected(&mut self, change: &Change, err: &Error) {
+            self.record(err.code(), change);
+        }
+        fn on_dispute_opened(&mut self, change: &Change, disputed: &Transaction) {
+            assert_eq!(Some(2.0), disputed.amount);
+            self.record("dispute", change);
+        }
+        fn on_account_locked(&mut self, change: &Change) {
+            self.record("locked", change);
+        }
+        fn on_account_held(&mut self, change: &Change) {
+            self.record("held", change);
+        }
+    }
+
+    fn tx(kind: TransactionKind, tx: u32, amount: Option<f32>) -> Transaction {
+        Transaction {
+            kind,
+            client_id: 1,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        use TransactionKind::*;
+        let events = Arc::default();
+        let db = Db::default().with_observer(Recorder(Arc::clone(&events)));
+        db.clean();
+        let _ = db.process(tx(Deposit, 1, Some(2.0)));
+        let _ = db.process(tx(Withdrawal, 2, Some(3.0)));
+        let _ = db.process(tx(Dispute, 1, None));
+        let _ = db.process(tx(Chargeback, 1, None));
+        let _ = db.process(tx(Deposit, 3, Some(1.0)));
+        db.clean();
+        assert_eq!(
+            vec![
+                "applied:1:>2/0",
+                "account_withdraw:2:2/0>2/0",
+                "applied:1:2/0>0/2",
+                "dispute:1:2/0>0/2",
+                "applied:1:0/2>0/0",
+                "locked:1:0/2>0/0",
+                "account_locked:3:0/0>0/0",
+            ],
+            *events.lock().unwrap()
+        );
+    }
+    #[test]
+    fn test_lock_without_row() {
+        let events = Arc::default();
+        let db = Db::default().with_observer(Recorder(Arc::clone(&events)));
+        db.hold_account(1, "velocity_withdrawals");
+        let _ = db.process(tx(TransactionKind::Deposit, 1, Some(2.0)));
+        db.hold_account(1, "velocity_withdrawals");
+        db.hold_account(1, "velocity_withdrawals");
+        db.lock_account(1, "velocity_disputes");
+        db.lock_account(1, "velocity_disputes");
+        assert_eq!(
+            vec!["applied:1:>2/0", "held:-:2/0>2/0", "locked:-:2/0>2/0"],
+            *events.lock().unwrap()
+        );
+        assert_eq!(
+            Some("velocity_disputes"),
+            db.account(1).as_ref().and_then(|a| a.reason())
+        );
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::{Account, Funds},
+    currency::Currency,
+    error::Error,
+};
+
+/// Account row of the `client,available,held,total,locked` format,
+/// or of the `client,currency,available,held,total,locked` format of accounts with currencies
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Balance {
+    pub client: u16,
+    /// Default currency if the file has no `currency` column
+    #[serde(default)]
+    pub currency: Currency,
+    pub available: f32,
+    pub held: f32,
+    pub total: f32,
+    pub locked: bool,
+}
+impl Balance {
+    /// Balances of every currency of the account, the same rows the engine writes for it
+    pub fn from_account(acc: &Account) -> Vec<Self> {
+        let mut funds: Vec<(Currency, Funds)> = acc.currencies().collect();
+        if funds.is_empty() {
+            funds.push((Currency::NONE, Funds::default()));
+        }
+        funds
+            .into_iter()
+            .map(|(currency, funds)| Balance {
+                client: acc.client_id(),
+                currency,
+                available: funds.available,
+                held: funds.held,
+                total: funds.total,
+                locked: acc.is_locked(),
+            })
+            .collect()
+    }
+    fn key(&self) -> (u16, Currency) {
+        (self.client, self.currency)
+    }
+}
+
+/// Reads an account file, spaces around fields and headers are allowed
+pub fn read_balances(rdr: impl Read) -> Result<Vec<Balance>, Error> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(rdr)
+        .deserialize()
+        .map(|row| row.map_err(Error::from))
+        .collect()
+}
+
+/// Difference of an account present in both files
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub actual: Balance,
+    pub expected: Balance,
+    /// Names of the fields that differ
+    pub fields: Vec<&'static str>,
+}
+
+/// Sums of the compared account files in one currency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Totals {
+    pub accounts: usize,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: usize,
+}
+impl Totals {
+    fn add(&mut self, balance: &Balance) {
+        self.accounts += 1;
+        self.available += balance.available as f64;
+        self.held += balance.held as f64;
+        self.total += balance.total as f64;
+        self.locked += balance.locked as usize;
+    }
+}
+
+/// Balances by client and currency and the rows of clients listed more than once in a currency
+fn by_client(balances: &[Balance]) -> (BTreeMap<(u16, Currency), Balance>, Vec<Balance>) {
+    let mut rows = BTreeMap::<(u16, Currency), Vec<Balance>>::new();
+    for balance in balances {
+        rows.entry(balance.key()).or_default().push(*balance);
+    }
+    let mut unique = BTreeMap::new();
+    let mut duplicates = vec![];
+    for (key, rows) in rows {
+        match rows.as_slice() {
+            [balance] => {
+                unique.insert(key, *balance);
+            }
+            _ => duplicates.extend(rows),
+        }
+    }
+    (unique, duplicates)
+}
+
+/// Sums of the balances by currency
+fn totals(balances: &[Balance]) -> BTreeMap<Currency, Totals> {
+    let mut totals = BTreeMap::<Currency, Totals>::new();
+    for balance in balances {
+        totals.entry(balance.currency).or_default().add(balance);
+    }
+    totals
+}
+
+/// Row of the reconciliation report, at least one of the balances is present
+struct ReportRow<'a> {
+    status: &'static str,
+    fields: String,
+    actual: Option<&'a Balance>,
+    expected: Option<&'a Balance>,
+}
+impl ReportRow<'_> {
+    fn key(&self) -> (u16, Currency) {
+        self.actual
+            .or(self.expected)
+            .map(Balance::key)
+            .unwrap_or_default()
+    }
+}
+
+/// Result of matching actual accounts against expected ones by client and currency
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reconciliation {
+    pub matched: usize,
+    pub mismatched: Vec<Mismatch>,
+    /// Expected accounts that are not in the actual ones
+    pub missing: Vec<Balance>,
+    /// Actual accounts that are not expected
+    pub extra: Vec<Balance>,
+    /// Rows of clients listed more than once in a currency of the actual accounts,
+    /// such balances are not compared
+    pub duplicate_actual: Vec<Balance>,
+    /// Rows of clients listed more than once in a currency of the expected accounts,
+    /// such balances are not compared
+    pub duplicate_expected: Vec<Balance>,
+    pub actual_totals: BTreeMap<Currency, Totals>,
+    pub expected_totals: BTreeMap<Currency, Totals>,
+}
+impl Reconciliation {
+    /// Compares the balances of every client and currency, amounts with the absolute `tolerance`
+    /// and the locked flag exactly. Balances are reported ordered by client and currency.
+    /// A client listed more than once in a currency of a file is reported as a duplicate
+    /// with all of those rows and that currency is not compared; totals include every row
+    pub fn new(actual: &[Balance], expected: &[Balance], tolerance: f32) -> Self {
+        let mut result = Reconciliation {
+            actual_totals: totals(actual),
+            expected_totals: totals(expected),
+            ..Default::default()
+        };
+        let (actual, duplicate_actual) = by_client(actual);
+        let (expected, duplicate_expected) = by_client(expected);
+        let duplicated: BTreeSet<(u16, Currency)> = duplicate_actual
+            .iter()
+            .chain(&duplicate_expected)
+            .map(Balance::key)
+            .collect();
+        result.duplicate_actual = duplicate_actual;
+        result.duplicate_expected = duplicate_expected;
+        for (key, exp) in &expected {
+            if duplicated.contains(key) {
+                continue;
+            }
+            let Some(act) = actual.get(key) else {
+                result.missing.push(*exp);
+                continue;
+            };
+            let differs = |a: f32, b: f32| (a - b).abs() > tolerance || a.is_nan() != b.is_nan();
+            let fields: Vec<&'static str> = [
+                ("available", differs(act.available, exp.available)),
+                ("held", differs(act.held, exp.held)),
+                ("total", differs(act.total, exp.total)),
+                ("locked", act.locked != exp.locked),
+            ]
+            .into_iter()
+            .filter_map(|(name, differs)| differs.then_some(name))
+            .collect();
+            if fields.is_empty() {
+                result.matched += 1;
+            } else {
+                result.mismatched.push(Mismatch {
+                    actual: *act,
+                    expected: *exp,
+                    fields,
+                });
+            }
+        }
+        result.extra = actual
+            .into_iter()
+            .filter(|(key, _)| !expected.contains_key(key) && !duplicated.contains(key))
+            .map(|(_, balance)| balance)
+            .collect();
+        result
+    }
+    /// Returns `true` if every account matches
+    pub fn is_clean(&self) -> bool {
+        self.mismatched.is_empty()
+            && self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.duplicate_actual.is_empty()
+            && self.duplicate_expected.is_empty()
+    }
+    /// Writes a row for every mismatched, missing, extra and duplicate balance, ordered by client and currency:
+    /// `client,currency,status,fields,available,held,total,locked,expected_available,expected_held,expected_total,expected_locked`
+    pub fn write_report(&self, w: impl Write) -> Result<(), Error> {
+        let mut wtr = csv::Writer::from_writer(w);
+        wtr.write_record([
+            "client",
+            "currency",
+            "status",
+            "fields",
+            "available",
+            "held",
+            "total",
+            "locked",
+            "expected_available",
+            "expected_held",
+            "expected_total",
+            "expected_locked",
+        ])?;
+        let mut rows: Vec<ReportRow> = self
+            .mismatched
+            .iter()
+            .map(|m| ReportRow {
+                status: "mismatch",
+                fields: m.fields.join(";"),
+                actual: Some(&m.actual),
+                expected: Some(&m.expected),
+            })
+            .chain(self.missing.iter().map(|b| ReportRow {
+                status: "missing",
+                fields: String::new(),
+                actual: None,
+                expected: Some(b),
+            }))
+            .chain(self.extra.iter().map(|b| ReportRow {
+                status: "extra",
+                fields: String::new(),
+                actual: Some(b),
+                expected: None,
+            }))
+            .chain(self.duplicate_actual.iter().map(|b| ReportRow {
+                status: "duplicate",
+                fields: String::new(),
+                actual: Some(b),
+                expected: None,
+            }))
+            .chain(self.duplicate_expected.iter().map(|b| ReportRow {
+                status: "duplicate",
+                fields: String::new(),
+                actual: None,
+                expected: Some(b),
+            }))
+            .collect();
+        rows.sort_by_key(ReportRow::key);
+        let columns = |balance: Option<&Balance>| match balance {
+            Some(b) => [
+                b.available.to_string(),
+                b.held.to_string(),
+                b.total.to_string(),
+                b.locked.to_string(),
+            ],
+            None => Default::default(),
+        };
+        for row in rows {
+            let (client, currency) = row.key();
+            let mut record = vec![
+                client.to_string(),
+                currency.to_string(),
+                row.status.to_string(),
+                row.fields,
+            ];
+            record.extend(columns(row.actual));
+            record.extend(columns(row.expected));
+            wtr.write_record(record)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+impl Display for Reconciliation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "matched: {}, mismatched: {}, missing: {}, extra: {}, duplicate: {}",
+            self.matched,
+            self.mismatched.len(),
+            self.missing.len(),
+            self.extra.len(),
+            self.duplicate_actual.len() + self.duplicate_expected.len()
+        )?;
+        let currencies: BTreeSet<&Currency> = self
+            .actual_totals
+            .keys()
+            .chain(self.expected_totals.keys())
+            .collect();
+        for currency in currencies {
+            // the default currency has no code, so its lines are not labelled
+            let label = match currency.is_default() {
+                true => String::new(),
+                false => format!(" {}", currency),
+            };
+            let actual = self
+                .actual_totals
+                .get(currency)
+                .copied()
+                .unwrap_or_default();
+            let expected = self
+                .expected_totals
+                .get(currency)
+                .copied()
+                .unwrap_or_default();
+            for (name, totals) in [("actual", actual), ("expected", expected)] {
+                write!(
+                    f,
+                    "\n{}{} accounts: {}, available: {}, held: {}, total: {}, locked: {}",
+                    name,
+                    label,
+                    totals.accounts,
+                    totals.available,
+                    totals.held,
+                    totals.total,
+                    totals.locked
+                )?;
+            }
+            write!(
+                f,
+                "\ndifference{} available: {}, held: {}, total: {}",
+                label,
+                actual.available - expected.available,
+                actual.held - expected.held,
+                actual.total - expected.total
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_balances, Balance, Reconciliation};
+    use crate::{account::Account, currency::Currency};
+
+    #[test]
+    fn test_reconcile() {
+        let actual = read_balances(
+            "client, available, held, total, locked\n1,1.5,0,1.5,false\n2,2,0,2,false\n3,0,0,0,true\n4,1,0,1,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let expected = read_balances(
+            "client,available,held,total,locked\n1,1.50001,0,1.50001,false\n2,1,1,2,false\n3,0,0,0,false\n5,1,0,1,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let result = Reconciliation::new(&actual, &expected, 0.0001);
+        assert!(!result.is_clean());
+        assert_eq!(1, result.matched);
+        assert_eq!(vec!["available", "held"], result.mismatched[0].fields);
+        assert_eq!(vec!["locked"], result.mismatched[1].fields);
+        assert_eq!(5, result.missing[0].client);
+        assert_eq!(4, result.extra[0].client);
+        assert_eq!(4, result.actual_totals[&Currency::NONE].accounts);
+        assert_eq!(1, result.actual_totals[&Currency::NONE].locked);
+        let mut out = vec![];
+        result.write_report(&mut out).unwrap();
+        assert_eq!(
+            "client,currency,status,fields,available,held,total,locked,expected_available,expected_held,expected_total,expected_locked
+2,,mismatch,available;held,2,0,2,false,1,1,2,false
+3,,mismatch,locked,0,0,0,true,0,0,0,false
+4,,extra,,1,0,1,false,,,,
+5,,missing,,,,,,1,0,1,false
+",
+            String::from_utf8(out).unwrap()
+        );
+        assert!(Reconciliation::new(&actual, &actual, 0.0).is_clean());
+    }
+    #[test]
+    fn test_duplicates() {
+        let actual = read_balances(
+            "client,available,held,total,locked\n1,5,0,5,false\n1,999,0,999,false\n2,1,0,1,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let expected = read_balances(
+            "client,available,held,total,locked\n1,5,0,5,false\n2,1,0,1,false\n2,1,0,1,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let result = Reconciliation::new(&actual, &expected, 0.0001);
+        assert!(!result.is_clean());
+        assert_eq!(0, result.matched);
+        assert!(result.missing.is_empty() && result.extra.is_empty());
+        assert_eq!(2, result.duplicate_actual.len());
+        assert_eq!(2, result.duplicate_expected.len());
+        assert_eq!(3, result.actual_totals[&Currency::NONE].accounts);
+        let mut out = vec![];
+        result.write_report(&mut out).unwrap();
+        assert_eq!(
+            "client,currency,status,fields,available,held,total,locked,expected_available,expected_held,expected_total,expected_locked
+1,,duplicate,,5,0,5,false,,,,
+1,,duplicate,,999,0,999,false,,,,
+2,,duplicate,,,,,,1,0,1,false
+2,,duplicate,,,,,,1,0,1,false
+",
+            String::from_utf8(out).unwrap()
+        );
+    }
+    #[test]
+    fn test_currencies() {
+        let mut acc = Account::new(1);
+        acc.deposit(Currency::NONE, 1.0).unwrap();
+        acc.deposit("EUR".parse().unwrap(), 5.0).unwrap();
+        let actual = Balance::from_account(&acc);
+        assert_eq!(2, actual.len());
+        let expected = read_balances(
+            "client,currency,available,held,total,locked\n1,,1,0,1,false\n1,EUR,4,0,4,false\n1,USD,2,0,2,false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let result = Reconciliation::new(&actual, &expected, 0.0001);
+        assert_eq!(1, result.matched);
+        assert_eq!(1, result.mismatched.len());
+        assert_eq!(1, result.missing.len());
+        assert_eq!(3, result.expected_totals.len());
+        let mut out = vec![];
+        result.write_report(&mut out).unwrap();
+        assert_eq!(
+            "client,currency,status,fields,available,held,total,locked,expected_available,expected_held,expected_total,expected_locked
+1,EUR,mismatch,available;total,5,0,5,false,4,0,4,false
+1,USD,missing,,,,,,2,0,2,false
+",
+            String::from_utf8(out).unwrap()
+        );
+        assert!(result
+            .to_string()
+            .contains("\ndifference EUR available: 1, held: 0, total: 1"));
+        assert_eq!(
+            Balance::from_account(&Account::new(2))[0].currency,
+            Currency::NONE
+        );
+    }
+}
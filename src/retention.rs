@@ -0,0 +1,294 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::{
+    currency::Currency,
+    error::Error,
+    transaction::{Transaction, TransactionKind, TransactionState},
+};
+
+/// Defines how long a deposit or withdrawal can be disputed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeWindow {
+    /// Keep at most this number of transactions in memory
+    Count(usize),
+    /// Keep transactions for this number of processed rows
+    Age(u64),
+}
+
+/// Configuration of the transaction table size.
+/// Transactions out of the `window` are either spilled to the `spill` file or dropped
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Retention {
+    pub window: Option<DisputeWindow>,
+    pub spill: Option<PathBuf>,
+}
+
+/// Compact representation of a stored deposit or withdrawal.
+/// The transaction id is the key of the table, so it is not repeated here
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TxRecord {
+    pub client_id: u16,
+    /// Index of the currency in the `CurrencyTable` of the database
+    pub currency: u8,
+    /// State in the low bits, the high bit is set for deposits
+    flags: u8,
+    pub amount: f32,
+}
+impl TxRecord {
+    /// Record of the transaction whose currency has the index `currency`
+    pub fn new(tx: &Transaction, currency: u8) -> Self {
+        TxRecord {
+            client_id: tx.client_id,
+            currency,
+            flags: state_to_byte(&tx.state) | if tx.kind.is_deposit() { DEPOSIT } else { 0 },
+            amount: tx.amount.unwrap_or_default(),
+        }
+    }
+    pub fn is_deposit(&self) -> bool {
+        self.flags & DEPOSIT != 0
+    }
+    pub fn state(&self) -> TransactionState {
+        state_from_byte(self.flags & !DEPOSIT)
+            .expect("state of a record is checked when it is created")
+    }
+    pub fn to_transaction(self, tx: u32, currency: Currency) -> Transaction {
+        Transaction {
+            kind: if self.is_deposit() {
+                TransactionKind::Deposit
+            } else {
+                TransactionKind::Withdrawal
+            },
+            client_id: self.client_id,
+            tx,
+            amount: Some(self.amount),
+            currency,
+            state: self.state(),
+        }
+    }
+    fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut buf = [0; RECORD_SIZE];
+        buf[0] = self.flags;
+        buf[1] = self.currency;
+        buf[2..4].copy_from_slice(&self.client_id.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.amount.to_le_bytes());
+        buf
+    }
+    fn from_bytes(buf: [u8; RECORD_SIZE]) -> Option<Self> {
+        state_from_byte(buf[0] & !DEPOSIT)?;
+        Some(TxRecord {
+            client_id: u16::from_le_bytes([buf[2], buf[3]]),
+            currency: buf[1],
+            flags: buf[0],
+            amount: f32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        })
+    }
+}
+
+const RECORD_SIZE: usize = 8;
+const _: () = assert!(std::mem::size_of::<TxRecord>() == RECORD_SIZE);
+/// Flag of deposits in the first byte of a record
+const DEPOSIT: u8 = 0x80;
+
+/// Currencies of the stored transactions, a record refers to its currency by the index in the table.
+/// The default currency has the index `0`, so at most 255 other currencies can be stored.
+/// Indexes are only valid within one database, the spill file is recreated on every run
+#[derive(Debug, Default)]
+pub struct CurrencyTable(Vec<Currency>);
+impl CurrencyTable {
+    /// Index of the currency, the currency is added if it is new. `None` if the table is full
+    pub fn index(&mut self, currency: Currency) -> Option<u8> {
+        if currency.is_default() {
+            return Some(0);
+        }
+        let pos = match self.0.iter().position(|c| *c == currency) {
+            Some(pos) => pos,
+            None if self.0.len() < u8::MAX as usize => {
+                self.0.push(currency);
+                self.0.len() - 1
+            }
+            None => return None,
+        };
+        Some(pos as u8 + 1)
+    }
+    /// Index of a currency already in the table
+    pub fn find(&self, currency: Currency) -> Option<u8> {
+        if currency.is_default() {
+            return Some(0);
+        }
+        self.0
+            .iter()
+            .position(|c| *c == currency)
+            .map(|pos| pos as u8 + 1)
+    }
+    pub fn get(&self, index: u8) -> Currency {
+        match index {
+            0 => Currency::NONE,
+            i => self.0.get(i as usize - 1).copied().unwrap_or_default(),
+        }
+    }
+}
+/// `0` marks an empty slot of the spill file
+fn state_to_byte(state: &TransactionState) -> u8 {
+    match state {
+        TransactionState::Processing => 1,
+        TransactionState::Dispute => 2,
+        TransactionState::Completed => 3,
+        TransactionState::Resolved => 4,
+        TransactionState::Chargeback => 5,
+    }
+}
+fn state_from_byte(b: u8) -> Option<TransactionState> {
+    match b {
+        1 => Some(TransactionState::Processing),
+        2 => Some(TransactionState::Dispute),
+        3 => Some(TransactionState::Completed),
+        4 => Some(TransactionState::Resolved),
+        5 => Some(TransactionState::Chargeback),
+        _ => None,
+    }
+}
+
+/// On-disk index of evicted transactions.
+/// Every transaction id has a fixed slot at `tx_id * RECORD_SIZE`, so the file is sparse and needs no in-memory index
+#[derive(Debug)]
+pub struct SpillFile {
+    file: File,
+}
+impl SpillFile {
+    pub fn create(path: &PathBuf) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(SpillFile { file })
+    }
+    pub fn write(&mut self, tx_id: u32, record: TxRecord) -> Result<(), Error> {
+        self.file
+            .seek(SeekFrom::Start(tx_id as u64 * RECORD_SIZE as u64))
+            .and_then(|_| self.file.write_all(&record.to_bytes()))
+            .map_err(Error::Io)
+    }
+    pub fn read(&mut self, tx_id: u32) -> Result<Option<TxRecord>, Error> {
+        let mut buf = [0; RECORD_SIZE];
+        let read = self
+            .file
+            .seek(SeekFrom::Start(tx_id as u64 * RECORD_SIZE as u64))
+            .and_then(|_| self.file.read_exact(&mut buf));
+        match read {
+            Ok(()) => Ok(TxRecord::from_bytes(buf)),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+}
+
+/// Sparse bit set of transaction ids that left the in-memory table.
+/// Only blocks of 64 ids with an evicted id are stored, so the size follows the evicted ids, not the largest id
+#[derive(Debug, Default)]
+pub struct EvictedSet(BTreeMap<u32, u64>);
+impl EvictedSet {
+    pub fn insert(&mut self, tx_id: u32) {
+        *self.0.entry(tx_id / 64).or_default() |= 1 << (tx_id % 64);
+    }
+    pub fn contains(&self, tx_id: u32) -> bool {
+        self.0
+            .get(&(tx_id / 64))
+            .is_some_and(|x| x & (1 << (tx_id % 64)) != 0)
+    }
+    /// Evicted ids in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.0.iter().flat_map(|(block, x)| {
+            (0..64)
+                .filter(move |bit| x & (1 << bit) != 0)
+                .map(move |bit| block * 64 + bit)
+        })
+    }
+}
+
+/// Runtime state of the retention: order of stored transactions, evicted ids and the spill file
+#[derive(Debug, Default)]
+pub struct History {
+    /// Number of processed rows
+    pub seq: u64,
+    /// Stored transaction ids with the row number they were stored at, oldest first
+    pub queue: VecDeque<(u64, u32)>,
+    pub evicted: EvictedSet,
+    pub spill: Option<SpillFile>,
+}
+impl History {
+    /// Returns `true` when the oldest stored transaction is out of the `window`
+    pub fn is_exceeded(&self, window: &DisputeWindow, stored: usize) -> bool {
+        match window {
+            DisputeWindow::Count(n) => stored > *n,
+            DisputeWindow::Age(age) => self
+                .queue
+                .front()
+                .is_some_and(|(seq, _)| seq + age < self.seq),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CurrencyTable, EvictedSet, SpillFile, TxRecord};
+    use crate::{
+        currency::Currency,
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    #[test]
+    fn test_evicted_set() {
+        let mut set = EvictedSet::default();
+        assert!(!set.contains(1));
+        set.insert(1);
+        set.insert(130);
+        assert!(set.contains(1));
+        assert!(set.contains(130));
+        assert!(!set.contains(2));
+        assert!(!set.contains(u32::MAX));
+        set.insert(u32::MAX);
+        assert_eq!(vec![1, 130, u32::MAX], set.iter().collect::<Vec<_>>());
+    }
+    #[test]
+    fn test_currency_table() {
+        let mut table = CurrencyTable::default();
+        let usd: Currency = "USD".parse().unwrap();
+        assert_eq!(Some(0), table.index(Currency::NONE));
+        assert_eq!(None, table.find(usd));
+        assert_eq!(Some(1), table.index(usd));
+        assert_eq!(Some(1), table.index(usd));
+        assert_eq!(usd, table.get(1));
+        for i in 2..=255 {
+            let code: Currency = format!("C{}", i).parse().unwrap();
+            assert_eq!(Some(i as u8), table.index(code));
+        }
+        assert_eq!(None, table.index("EUR".parse().unwrap()));
+        assert_eq!(Some(1), table.find(usd));
+    }
+    #[test]
+    fn test_spill_file() {
+        let path = std::env::temp_dir().join(format!("spill-{}.bin", std::process::id()));
+        let mut spill = SpillFile::create(&path).unwrap();
+        let tx = Transaction {
+            kind: TransactionKind::Deposit,
+            client_id: 3,
+            tx: 10,
+            amount: Some(1.5),
+            currency: Currency::NONE,
+            state: TransactionState::Dispute,
+        };
+        let record = TxRecord::new(&tx, 1);
+        assert!(record.is_deposit());
+        assert_eq!(TransactionState::Dispute, record.state());
+        assert_eq!(Ok(()), spill.write(10, record));
+        assert_eq!(Ok(Some(record)), spill.read(10));
+        assert_eq!(Ok(None), spill.read(5));
+        assert_eq!(Ok(None), spill.read(100));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+
+use serde::Deserialize;
+
+use crate::{
+    currency::Currency,
+    error::Error,
+    transaction::{Transaction, TransactionKind},
+};
+
+/// Check of a row before it is processed, `Send` so a chain can be moved with its engine
+pub trait Rule: Send {
+    /// Stable reason reported for rows failing the rule, like `Error::code`
+    fn code(&self) -> &'static str;
+    fn check(&self, tx: &Transaction) -> bool;
+}
+
+/// Deposits and withdrawals must have an amount, other kinds must not
+pub struct AmountPresence;
+impl Rule for AmountPresence {
+    fn code(&self) -> &'static str {
+        "tx_invalid"
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        tx.amount.is_some()
+            == matches!(
+                tx.kind,
+                TransactionKind::Deposit | TransactionKind::Withdrawal
+            )
+    }
+}
+
+/// Amounts must be positive and finite, a zero deposit or withdrawal moves no money
+/// and an infinite one would let every later withdrawal succeed
+pub struct PositiveAmount;
+impl Rule for PositiveAmount {
+    fn code(&self) -> &'static str {
+        "tx_invalid"
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        tx.amount.is_none_or(|x| x.is_finite() && x > 0.0)
+    }
+}
+
+/// Amounts of the given kinds, or of all kinds if none are given, must be within `min..=max`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AmountRange {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+    #[serde(default)]
+    pub kinds: Vec<TransactionKind>,
+}
+impl Rule for AmountRange {
+    fn code(&self) -> &'static str {
+        "rule_amount_range"
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        let Some(amount) = tx.amount else {
+            return true;
+        };
+        if !self.kinds.is_empty() && !self.kinds.contains(&tx.kind) {
+            return true;
+        }
+        self.min.is_none_or(|min| amount >= min) && self.max.is_none_or(|max| amount <= max)
+    }
+}
+
+/// Amounts can have at most `places` decimal places.
+/// Amounts are `f32`, so digits beyond its precision are not checked
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct MaxDecimals {
+    pub places: u32,
+}
+impl Rule for MaxDecimals {
+    fn code(&self) -> &'static str {
+        "rule_decimals"
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        tx.amount.is_none_or(|x| has_decimals(x, self.places))
+    }
+}
+/// Returns `true` if `amount` has at most `places` decimal places
+fn has_decimals(amount: f32, places: u32) -> bool {
+    let scaled = amount as f64 * 10f64.powi(places as i32);
+    (scaled - scaled.round()).abs() <= (scaled.abs() * f32::EPSILON as f64).max(1e-3)
+}
+
+/// Rows with a currency must use a listed one, with at most its number of decimal places.
+/// Rows without a currency are not checked
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct CurrencyList {
+    pub currencies: BTreeMap<Currency, u32>,
+}
+impl Rule for CurrencyList {
+    fn code(&self) -> &'static str {
+        "rule_currency"
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        if tx.currency.is_default() {
+            return true;
+        }
+        match self.currencies.get(&tx.currency) {
+            Some(places) => tx.amount.is_none_or(|x| has_decimals(x, *places)),
+            None => false,
+        }
+    }
+}
+
+/// Only the listed clients can have rows
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AllowedClients {
+    pub clients: HashSet<u16>,
+}
+impl Rule for AllowedClients {
+    fn code(&self) -> &'static str {
+        "rule_client"
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        self.clients.contains(&tx.client_id)
+    }
+}
+
+/// Rows of the listed kinds are rejected
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct BlockedKinds {
+    pub kinds: Vec<TransactionKind>,
+}
+impl Rule for BlockedKinds {
+    fn code(&self) -> &'static str {
+        "rule_kind"
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        !self.kinds.contains(&tx.kind)
+    }
+}
+
+/// Custom check of a row added from the library
+pub struct CustomRule<F> {
+    pub code: &'static str,
+    pub check: F,
+}
+impl<F: Fn(&Transaction) -> bool + Send> Rule for CustomRule<F> {
+    fn code(&self) -> &'static str {
+        self.code
+    }
+    fn check(&self, tx: &Transaction) -> bool {
+        (self.check)(tx)
+    }
+}
+
+/// Rule as it is written in a config file, e.g. `{"rule": "amount_range", "max": 10000}`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum RuleConfig {
+    AmountRange(AmountRange),
+    MaxDecimals(MaxDecimals),
+    AllowedClients(AllowedClients),
+    BlockedKinds(BlockedKinds),
+    CurrencyList(CurrencyList),
+}
+impl RuleConfig {
+    fn into_rule(self) -> Box<dyn Rule> {
+        match self {
+            Self::AmountRange(rule) => Box::new(rule),
+            Self::MaxDecimals(rule) => Box::new(rule),
+            Self::AllowedClients(rule) => Box::new(rule),
+            Self::BlockedKinds(rule) => Box::new(rule),
+            Self::CurrencyList(rule) => Box::new(rule),
+        }
+    }
+}
+
+/// Ordered rules, a row is rejected with the code of the first rule it fails.
+/// The default chain has the checks of `Transaction::is_valid`
+pub struct RuleChain(Vec<Box<dyn Rule>>);
+impl Default for RuleChain {
+    fn default() -> Self {
+        RuleChain(vec![Box::new(AmountPresence), Box::new(PositiveAmount)])
+    }
+}
+impl RuleChain {
+    /// Chain without any rules, not even the default ones
+    pub fn empty() -> Self {
+        RuleChain(vec![])
+    }
+    /// Default rules followed by the rules of a JSON config: `{"rules": [{"rule": "max_decimals", "places": 4}]}`
+    pub fn from_json(rdr: impl Read) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct Config {
+            rules: Vec<RuleConfig>,
+        }
+        let config: Config = serde_json::from_reader(rdr)?;
+        Ok(config
+            .rules
+            .into_iter()
+            .fold(Self::default(), |chain, rule| {
+                chain.with_boxed(rule.into_rule())
+            }))
+    }
+    pub fn with_rule(self, rule: impl Rule + 'static) -> Self {
+        self.with_boxed(Box::new(rule))
+    }
+    fn with_boxed(mut self, rule: Box<dyn Rule>) -> Self {
+        self.0.push(rule);
+        self
+    }
+    pub fn check(&self, tx: &Transaction) -> Result<(), Error> {
+        match self.0.iter().find(|rule| !rule.check(tx)) {
+            Some(rule) => Err(Error::Rule(rule.code())),
+            None => Ok(()),
+        }
+    }
+}
+impl std::fmt::Debug for RuleChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|rule| rule.code()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CustomRule, RuleChain};
+    use crate::{
+        currency::Currency,
+        error::Error,
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    fn tx(kind: TransactionKind, client_id: u16, amount: Option<f32>) -> Transaction {
+        Transaction {
+            kind,
+            client_id,
+            tx: 1,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        }
+    }
+
+    #[test]
+    fn test_default_rules() {
+        use TransactionKind::*;
+        let chain = RuleChain::default();
+        for t in [
+            tx(Deposit, 1, Some(1.0)),
+            tx(Deposit, 1, None),
+            tx(Withdrawal, 1, Some(-1.0)),
+            tx(Deposit, 1, Some(0.0)),
+            tx(Deposit, 1, Some(f32::INFINITY)),
+            tx(Withdrawal, 1, Some(f32::NAN)),
+            tx(Dispute, 1, None),
+            tx(Dispute, 1, Some(1.0)),
+            tx(Chargeback, 1, Some(0.0)),
+        ] {
+            assert_eq!(t.is_valid(), chain.check(&t).is_ok(), "{}", t);
+        }
+    }
+    #[test]
+    fn test_config() {
+        use TransactionKind::*;
+        let config = r#"{"rules": [
+            {"rule": "blocked_kinds", "kinds": ["chargeback"]},
+            {"rule": "amount_range", "min": 0.01, "max": 100, "kinds": ["withdrawal"]},
+            {"rule": "max_decimals", "places": 2},
+            {"rule": "allowed_clients", "clients": [1, 2]}
+        ]}"#;
+        let chain = RuleChain::from_json(config.as_bytes())
+            .unwrap()
+            .with_rule(CustomRule {
+                code: "rule_custom",
+                check: |t: &Transaction| t.client_id != 2 || t.kind != Dispute,
+            });
+        let code = |t| chain.check(&t).err().map(|e| e.code());
+        assert_eq!(None, code(tx(Deposit, 1, Some(1000.25))));
+        assert_eq!(None, code(tx(Withdrawal, 1, Some(100.0))));
+        assert_eq!(Some("tx_invalid"), code(tx(Withdrawal, 1, Some(-1.0))));
+        assert_eq!(Some("tx_invalid"), code(tx(Deposit, 1, Some(0.0))));
+        let inf = "type,client,tx,amount\ndeposit,1,1,inf\n";
+        let inf = csv::Reader::from_reader(inf.as_bytes())
+            .deserialize::<Transaction>()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(Some(f32::INFINITY), inf.amount);
+        assert_eq!(Some("tx_invalid"), code(inf));
+        assert_eq!(
+            Some("rule_amount_range"),
+            code(tx(Withdrawal, 1, Some(100.5)))
+        );
+        assert_eq!(Some("rule_decimals"), code(tx(Deposit, 1, Some(1.125))));
+        assert_eq!(Some("rule_kind"), code(tx(Chargeback, 1, None)));
+        assert_eq!(Some("rule_client"), code(tx(Deposit, 3, Some(1.0))));
+        assert_eq!(Some("rule_custom"), code(tx(Dispute, 2, None)));
+        assert_eq!(
+            Some("parse"),
+            RuleChain::from_json(r#"{"rules": [{"rule": "unknown"}]}"#.as_bytes())
+                .err()
+                .as_ref()
+                .map(Error::code)
+        );
+    }
+    #[test]
+    fn test_currency_list() {
+        use TransactionKind::*;
+        let config =
+            r#"{"rules": [{"rule": "currency_list", "currencies": {"USD": 2, "btc": 8}}]}"#;
+        let chain = RuleChain::from_json(config.as_bytes()).unwrap();
+        let code = |currency: &str, amount| {
+            let t = Transaction {
+                currency: currency.parse().unwrap(),
+                ..tx(Deposit, 1, amount)
+            };
+            chain.check(&t).err().map(|e| e.code())
+        };
+        assert_eq!(None, code("", Some(1.125)));
+        assert_eq!(None, code("USD", Some(1.25)));
+        assert_eq!(None, code("BTC", Some(0.00012345)));
+        assert_eq!(Some("rule_currency"), code("USD", Some(1.125)));
+        assert_eq!(Some("rule_currency"), code("EUR", Some(1.0)));
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::Error;
+
+/// How often the list file is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Meaning of the listed clients
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ScreeningMode {
+    /// Rows of listed clients are rejected
+    #[default]
+    Block,
+    /// Rows of clients that are not listed are rejected
+    Allow,
+}
+
+/// List of client ids screened before rows are processed.
+/// The list is read from a file with one client id per line, `#` starts a comment.
+/// The file is reloaded when it changes, so the list can be updated while a stream is processed
+#[derive(Debug, Default)]
+pub struct Screening {
+    mode: ScreeningMode,
+    clients: HashSet<u16>,
+    /// Lock existing accounts of screened clients
+    lock: bool,
+    file: Option<ListFile>,
+}
+
+#[derive(Debug)]
+struct ListFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Screening {
+    pub fn new(mode: ScreeningMode, clients: impl IntoIterator<Item = u16>) -> Self {
+        Screening {
+            mode,
+            clients: clients.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+    /// Reads the list from the file and keeps watching it for changes
+    pub fn from_file(mode: ScreeningMode, path: &Path) -> Result<Self, Error> {
+        let mut screening = Screening {
+            mode,
+            file: Some(ListFile {
+                path: path.to_path_buf(),
+                modified: None,
+                checked: Instant::now(),
+            }),
+            ..Default::default()
+        };
+        screening.reload()?;
+        Ok(screening)
+    }
+    pub fn with_lock(self, lock: bool) -> Self {
+        Screening { lock, ..self }
+    }
+    pub fn is_locking(&self) -> bool {
+        self.lock
+    }
+    pub fn is_empty(&self) -> bool {
+        self.mode == ScreeningMode::Block && self.clients.is_empty()
+    }
+    pub fn is_screened(&self, client_id: u16) -> bool {
+        match self.mode {
+            ScreeningMode::Block => self.clients.contains(&client_id),
+            ScreeningMode::Allow => !self.clients.contains(&client_id),
+        }
+    }
+    /// Reads the list file again, returns `false` if there is no file
+    pub fn reload(&mut self) -> Result<bool, Error> {
+        let Some(file) = self.file.as_mut() else {
+            return Ok(false);
+        };
+        file.checked = Instant::now();
+        file.modified = std::fs::metadata(&file.path)?.modified().ok();
+        self.clients = parse(&std::fs::read_to_string(&file.path)?)?;
+        log::info!(
+            "Loaded {} clients from {}",
+            self.clients.len(),
+            file.path.display()
+        );
+        Ok(true)
+    }
+    /// Reloads the list if the file changed since it was read, at most once per `RELOAD_INTERVAL`.
+    /// Returns `true` if the list is reloaded. On error the previous list is kept
+    pub fn refresh(&mut self) -> bool {
+        let Some(file) = self.file.as_mut() else {
+            return false;
+        };
+        if file.checked.elapsed() < RELOAD_INTERVAL {
+            return false;
+        }
+        file.checked = Instant::now();
+        let modified = std::fs::metadata(&file.path)
+            .and_then(|m| m.modified())
+            .ok();
+        if modified.is_none() || modified == file.modified {
+            return false;
+        }
+        match self.reload() {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                log::error!("{} while reloading the screening list", e);
+                false
+            }
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<HashSet<u16>, Error> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            line.parse()
+                .map_err(|_| Error::Config(format!("invalid client id {:?} on line {}", line, i)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{Screening, ScreeningMode};
+    use crate::error::Error;
+
+    #[test]
+    fn test_modes() {
+        let block = Screening::new(ScreeningMode::Block, [1, 2]);
+        assert!(block.is_screened(1));
+        assert!(!block.is_screened(3));
+        let allow = Screening::new(ScreeningMode::Allow, [1, 2]);
+        assert!(!allow.is_screened(1));
+        assert!(allow.is_screened(3));
+        assert!(Screening::default().is_empty());
+        assert!(!Screening::new(ScreeningMode::Allow, []).is_empty());
+    }
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join(format!("jones-screening-{}", std::process::id()));
+        std::fs::write(&path, "# sanctioned\n1\n 2 # comment\n\n").unwrap();
+        let mut screening = Screening::from_file(ScreeningMode::Block, &path).unwrap();
+        assert!(screening.is_screened(2));
+        assert!(!screening.refresh());
+        std::fs::write(&path, "3\n").unwrap();
+        // make the change visible on file systems with coarse timestamps
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        screening.file.as_mut().unwrap().checked -= Duration::from_secs(2);
+        assert!(screening.refresh());
+        assert!(!screening.is_screened(2));
+        assert!(screening.is_screened(3));
+        std::fs::write(&path, "3\nx\n").unwrap();
+        assert_eq!(
+            Err(Error::Config(
+                "invalid client id \"x\" on line 2".to_string()
+            )),
+            screening.reload()
+        );
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    account::{Account, Funds},
+    currency::Currency,
+};
+
+/// Account states recorded for point-in-time queries.
+/// The full account is recorded when it first appears and when it is locked or put on hold,
+/// in between only the funds changed by every applied row are recorded. Every snapshot interval
+/// the changed accounts are recorded in full again, so a query replays the funds of at most one interval
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    /// Clients whose funds changed since their last full state
+    dirty: HashSet<u16>,
+    /// Full states of every client with the sequence number they were recorded at, oldest first
+    accounts: HashMap<u16, Vec<(u64, Account)>>,
+    /// Funds of one currency after every row that changed them, oldest first
+    funds: HashMap<u16, Vec<(u64, Currency, Funds)>>,
+}
+impl Snapshots {
+    /// Records the full account as of the row `seq`
+    pub fn record(&mut self, seq: u64, acc: &Account) {
+        self.dirty.remove(&acc.client_id());
+        self.accounts
+            .entry(acc.client_id())
+            .or_default()
+            .push((seq, acc.clone()));
+    }
+    /// Records the account after the row `seq`, `applied` is the currency whose funds the row changed.
+    /// A rejected row only records an account it created
+    pub fn record_row(&mut self, seq: u64, acc: &Account, applied: Option<Currency>) {
+        let client_id = acc.client_id();
+        match applied {
+            _ if !self.accounts.contains_key(&client_id) => self.record(seq, acc),
+            // a locked account rejects every row, so the row locked it
+            Some(_) if acc.is_locked() => self.record(seq, acc),
+            Some(currency) => {
+                self.dirty.insert(client_id);
+                self.funds
+                    .entry(client_id)
+                    .or_default()
+                    .push((seq, currency, acc.funds(currency)));
+            }
+            None => {}
+        }
+    }
+    /// Records the full state of the accounts changed since their last full state
+    pub fn compact(&mut self, seq: u64, accounts: &HashMap<u16, Account>) {
+        for client_id in self.dirty.drain() {
+            if let Some(acc) = accounts.get(&client_id) {
+                self.accounts
+                    .entry(client_id)
+                    .or_default()
+                    .push((seq, acc.clone()));
+            }
+        }
+    }
+    /// Account of the client as of the row `seq`: the last full state at or before it
+    /// with the funds of the later rows up to `seq` replayed
+    pub fn at(&self, client_id: u16, seq: u64) -> Option<Account> {
+        let states = self.accounts.get(&client_id)?;
+        let i = states.partition_point(|(s, _)| *s <= seq).checked_sub(1)?;
+        let (recorded, acc) = &states[i];
+        let mut acc = acc.clone();
+        let funds = self.funds.get(&client_id).map_or(&[][..], Vec::as_slice);
+        let start = funds.partition_point(|(s, _, _)| s <= recorded);
+        for (_, currency, f) in funds[start..].iter().take_while(|(s, _, _)| *s <= seq) {
+            acc.set_funds(*currency, *f);
+        }
+        Some(acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Snapshots;
+    use crate::{account::Account, currency::Currency};
+
+    #[test]
+    fn test_at() {
+        let mut snapshots = Snapshots::default();
+        let mut acc = Account::new(1);
+        acc.deposit(Currency::NONE, 1.0).unwrap();
+        snapshots.record_row(2, &acc, Some(Currency::NONE));
+        acc.deposit(Currency::NONE, 2.0).unwrap();
+        snapshots.record_row(3, &acc, Some(Currency::NONE));
+        snapshots.record_row(4, &acc, None);
+        snapshots.compact(4, &HashMap::from([(1, acc.clone())]));
+        acc.withdraw(Currency::NONE, 0.5).unwrap();
+        snapshots.record_row(5, &acc, Some(Currency::NONE));
+        acc.lock("chargeback");
+        snapshots.record_row(7, &acc, Some(Currency::NONE));
+        let total = |seq| snapshots.at(1, seq).map(|a| a.total());
+        assert_eq!(None, total(1));
+        assert_eq!(Some(1.0), total(2));
+        assert_eq!(Some(3.0), total(3));
+        assert_eq!(Some(3.0), total(4));
+        assert_eq!(Some(2.5), total(5));
+        assert_eq!(Some(2.5), total(6));
+        assert_eq!(Some(false), snapshots.at(1, 6).map(|a| a.is_locked()));
+        assert_eq!(Some(true), snapshots.at(1, 100).map(|a| a.is_locked()));
+        assert!(snapshots.at(2, 6).is_none());
+    }
+}
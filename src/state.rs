@@ -0,0 +1,172 @@
+use std::io::{Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    error::Error,
+    transaction::{Transaction, TransactionKind, TransactionState},
+};
+
+/// Version of the state file format, a file of another version is not loaded
+pub const STATE_VERSION: u32 = 2;
+
+/// Stored deposit or withdrawal with its state
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StoredTx {
+    pub tx: u32,
+    pub client: u16,
+    #[serde(rename = "type")]
+    pub kind: TransactionKind,
+    pub amount: f32,
+    #[serde(default, skip_serializing_if = "Currency::is_default")]
+    pub currency: Currency,
+    pub state: TransactionState,
+    /// Row the transaction entered the dispute window at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+impl StoredTx {
+    pub fn new(tx: &Transaction, seq: Option<u64>) -> Self {
+        StoredTx {
+            tx: tx.tx,
+            client: tx.client_id,
+            kind: tx.kind,
+            amount: tx.amount.unwrap_or_default(),
+            currency: tx.currency,
+            state: tx.state,
+            seq,
+        }
+    }
+    pub fn to_transaction(&self) -> Transaction {
+        Transaction {
+            kind: self.kind,
+            client_id: self.client,
+            tx: self.tx,
+            amount: Some(self.amount),
+            currency: self.currency,
+            state: self.state,
+        }
+    }
+}
+
+/// Complete state of `Db`, written at the end of a run and loaded to continue with the next input
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    pub version: u32,
+    /// Version of the crate that wrote the state
+    pub engine: String,
+    /// Number of rows processed
+    pub seq: u64,
+    /// Accounts ordered by client
+    pub accounts: Vec<Account>,
+    /// Transactions in the dispute window, oldest first
+    pub transactions: Vec<StoredTx>,
+    /// Ids of transactions evicted out of the dispute window
+    pub evicted: Vec<u32>,
+    /// Evicted transactions read back from the spill file
+    pub spilled: Vec<StoredTx>,
+}
+impl Default for State {
+    fn default() -> Self {
+        State {
+            version: STATE_VERSION,
+            engine: env!("CARGO_PKG_VERSION").to_string(),
+            seq: 0,
+            accounts: vec![],
+            transactions: vec![],
+            evicted: vec![],
+            spilled: vec![],
+        }
+    }
+}
+impl State {
+    /// Reads a state written by `State::write`, fails on another format version.
+    /// The version is read first without building the rest of the file, so a state of another
+    /// format is reported as such instead of as a JSON error
+    pub fn read(mut rdr: impl Read) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+        }
+        let mut buf = vec![];
+        rdr.read_to_end(&mut buf)?;
+        let Header { version } = serde_json::from_slice(&buf)?;
+        if version != STATE_VERSION {
+            return Err(Error::StateVersion(version));
+        }
+        Ok(serde_json::from_slice(&buf)?)
+    }
+    pub fn write(&self, w: impl Write) -> Result<(), Error> {
+        serde_json::to_writer(w, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{State, STATE_VERSION};
+    use crate::{
+        currency::Currency,
+        db::Db,
+        error::Error,
+        retention::{DisputeWindow, Retention},
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    fn tx(kind: TransactionKind, tx: u32, amount: Option<f32>) -> Transaction {
+        Transaction {
+            kind,
+            client_id: 1,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        use TransactionKind::*;
+        let spill = std::env::temp_dir().join(format!("jones-state-{}", std::process::id()));
+        let db = Db::default().with_retention(Retention {
+            window: Some(DisputeWindow::Count(1)),
+            spill: Some(spill.clone()),
+        });
+        db.clean();
+        db.process(tx(Deposit, 1, Some(5.0))).unwrap();
+        db.process(tx(Deposit, 2, Some(3.0))).unwrap();
+        db.process(tx(Dispute, 2, None)).unwrap();
+        let mut file = vec![];
+        db.export_state().unwrap().write(&mut file).unwrap();
+        let state = State::read(file.as_slice()).unwrap();
+        assert_eq!(3, state.seq);
+        assert_eq!(vec![1], state.evicted);
+        assert_eq!(1, state.spilled.len());
+        assert_eq!(TransactionState::Dispute, state.transactions[0].state);
+
+        // the next run keeps every transaction in memory
+        let db = Db::default();
+        db.load_state(state).unwrap();
+        db.process(tx(Resolve, 2, None)).unwrap();
+        db.process(tx(Dispute, 1, None)).unwrap();
+        assert_eq!(
+            Err(Error::from(crate::transaction::TransactionError::Duplicate)),
+            db.process(tx(Deposit, 1, Some(1.0)))
+        );
+        let acc = db.account(1).unwrap();
+        assert_eq!((3.0, 5.0), (acc.available(), acc.held()));
+        assert_eq!(6, db.seq());
+        db.clean();
+        let _ = std::fs::remove_file(spill);
+    }
+    #[test]
+    fn test_version() {
+        let file = format!(r#"{{"version": {}, "engine": "0"}}"#, STATE_VERSION + 1);
+        assert_eq!(
+            Some("state_version"),
+            State::read(file.as_bytes()).err().as_ref().map(Error::code)
+        );
+    }
+}
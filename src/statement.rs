@@ -0,0 +1,347 @@
+use std::collections::HashSet;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{
+    account::{Account, Funds},
+    currency::Currency,
+    error::Error,
+    observer::{Change, Observer},
+    transaction::{Transaction, TransactionKind},
+};
+
+/// Format of statement entries
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum StatementFormat {
+    /// `client,tx,type,amount,currency,status,code,available,held,total,locked,note` with a header row
+    #[default]
+    Csv,
+    /// One JSON object per line with the fields of the CSV format
+    Json,
+}
+
+/// Row of a client with the account state after it, or a lock or hold of the account without a row
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Entry {
+    pub client: u16,
+    /// `None` for a lock or hold without a row
+    pub tx: Option<u32>,
+    #[serde(rename = "type")]
+    pub kind: Option<TransactionKind>,
+    /// Amount of the row, or the amount moved by an applied dispute, resolve or chargeback
+    pub amount: Option<f32>,
+    /// Currency of the row, or of the transaction an applied dispute, resolve or chargeback refers to
+    pub currency: Currency,
+    /// `applied` or `rejected` for rows, `locked` or `held` for accounts locked or put on hold
+    /// by a fraud rule or the screening
+    pub status: &'static str,
+    /// Error code of a rejected row, or the reason of a lock or hold
+    pub code: Option<String>,
+    pub available: f32,
+    pub held: f32,
+    pub total: f32,
+    pub locked: bool,
+    /// Dispute lifecycle and lock events separated by `;`
+    pub note: Option<String>,
+}
+impl Entry {
+    fn new(
+        tx: &Transaction,
+        change: &Change,
+        status: &'static str,
+        code: Option<&'static str>,
+    ) -> Self {
+        let acc = change.after.or(change.before);
+        let currency = moved_currency(tx, change);
+        let funds = |acc: Option<&Account>| acc.map(|a| a.funds(currency)).unwrap_or_default();
+        let held = |acc: Option<&Account>| funds(acc).held;
+        let mut amount = tx.amount;
+        let mut notes = vec![];
+        if code.is_none() {
+            let moved = (held(change.after) - held(change.before)).abs();
+            let event = match tx.kind {
+                TransactionKind::Dispute => Some("dispute_opened"),
+                TransactionKind::Resolve => Some("dispute_resolved"),
+                TransactionKind::Chargeback => Some("charged_back"),
+                _ => None,
+            };
+            if let Some(event) = event {
+                if moved > 0.0 {
+                    amount = Some(moved);
+                    notes.push(event);
+                } else {
+                    notes.push("reference_ignored");
+                }
+            }
+            let was_locked = change.before.is_some_and(|a| a.is_locked());
+            if !was_locked && change.after.is_some_and(|a| a.is_locked()) {
+                notes.push("account_locked");
+            }
+        }
+        Entry {
+            client: tx.client_id,
+            tx: Some(tx.tx),
+            kind: Some(tx.kind),
+            amount,
+            currency,
+            status,
+            code: code.map(str::to_string),
+            available: funds(acc).available,
+            held: held(acc),
+            total: funds(acc).total,
+            locked: acc.is_some_and(|a| a.is_locked()),
+            note: (!notes.is_empty()).then(|| notes.join(";")),
+        }
+    }
+    /// Entries of an account locked or put on hold without a row, one per currency of the account
+    fn account(change: &Change, status: &'static str, note: &str) -> Vec<Self> {
+        let Some(acc) = change.after else {
+            return vec![];
+        };
+        let mut funds: Vec<(Currency, Funds)> = acc.currencies().collect();
+        if funds.is_empty() {
+            funds.push((Currency::NONE, Funds::default()));
+        }
+        funds
+            .into_iter()
+            .map(|(currency, funds)| Entry {
+                client: change.client_id,
+                tx: None,
+                kind: None,
+                amount: None,
+                currency,
+                status,
+                code: acc.reason().map(str::to_string),
+                available: funds.available,
+                held: funds.held,
+                total: funds.total,
+                locked: acc.is_locked(),
+                note: Some(note.to_string()),
+            })
+            .collect()
+    }
+}
+
+/// Currency whose funds a dispute, resolve or chargeback moved, the currency of the row otherwise
+fn moved_currency(tx: &Transaction, change: &Change) -> Currency {
+    if matches!(
+        tx.kind,
+        TransactionKind::Deposit | TransactionKind::Withdrawal
+    ) {
+        return tx.currency;
+    }
+    let (Some(before), Some(after)) = (change.before, change.after) else {
+        return tx.currency;
+    };
+    after
+        .currencies()
+        .find(|(currency, funds)| before.funds(*currency) != *funds)
+        .map_or(tx.currency, |(currency, _)| currency)
+}
+
+enum Output<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Json(W),
+}
+
+/// Observer writing every applied and rejected row of the selected clients in order
+/// with the running balances after it, as well as locks and holds of fraud rules and the screening.
+/// Observer callbacks cannot fail, so the first write error is kept, nothing is written after it,
+/// and it is returned by `Observer::flush`
+pub struct Statement<W: Write> {
+    clients: HashSet<u16>,
+    out: Output<W>,
+    error: Option<Error>,
+}
+impl<W: Write> Statement<W> {
+    pub fn new(w: W, format: StatementFormat) -> Self {
+        let out = match format {
+            StatementFormat::Csv => Output::Csv(Box::new(csv::Writer::from_writer(w))),
+            StatementFormat::Json => Output::Json(w),
+        };
+        Statement {
+            clients: HashSet::new(),
+            out,
+            error: None,
+        }
+    }
+    /// Writes only rows of the given clients, all clients are written if none are given
+    pub fn with_clients(self, clients: impl IntoIterator<Item = u16>) -> Self {
+        Statement {
+            clients: clients.into_iter().collect(),
+            ..self
+        }
+    }
+    fn is_selected(&self, change: &Change) -> bool {
+        self.error.is_none()
+            && (self.clients.is_empty() || self.clients.contains(&change.client_id))
+    }
+    fn write_row(&mut self, change: &Change, status: &'static str, code: Option<&'static str>) {
+        if let Some(tx) = change.tx.filter(|_| self.is_selected(change)) {
+            self.write(&Entry::new(tx, change, status, code));
+        }
+    }
+    /// Writes a lock or hold that is not the result of a row, the locks of rows are in their notes
+    fn write_account(&mut self, change: &Change, status: &'static str, note: &str) {
+        if change.tx.is_some() || !self.is_selected(change) {
+            return;
+        }
+        for entry in Entry::account(change, status, note) {
+            self.write(&entry);
+        }
+    }
+    fn write(&mut self, entry: &Entry) {
+        let written: Result<(), Error> = match &mut self.out {
+            Output::Csv(wtr) => wtr.serialize(entry).map_err(Error::from),
+            Output::Json(w) => serde_json::to_writer(&mut *w, entry)
+                .map_err(Error::from)
+                .and_then(|_| Ok(writeln!(w)?)),
+        };
+        if let Err(e) = written {
+            log::error!("{} while writing the statement", e);
+            self.error.get_or_insert(e);
+        }
+    }
+}
+impl<W: Write + Send> Observer for Statement<W> {
+    fn on_applied(&mut self, change: &Change) {
+        self.write_row(change, "applied", None);
+    }
+    fn on_rejected(&mut self, change: &Change, err: &Error) {
+        self.write_row(change, "rejected", Some(err.code()));
+    }
+    fn on_account_locked(&mut self, change: &Change) {
+        self.write_account(change, "locked", "account_locked");
+    }
+    fn on_account_held(&mut self, change: &Change) {
+        self.write_account(change, "held", "account_on_hold");
+    }
+    fn flush(&mut self) -> Result<(), Error> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match &mut self.out {
+            Output::Csv(wtr) => wtr.flush()?,
+            Output::Json(w) => w.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use super::{Statement, StatementFormat};
+    use crate::{
+        engine::Engine,
+        error::Error,
+        fraud::{Action, Fraud, FraudRule},
+    };
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn statement(format: StatementFormat, input: &str) -> String {
+        let out = Shared::default();
+        let mut engine =
+            Engine::new().with_observer(Statement::new(out.clone(), format).with_clients([1]));
+        engine.process_reader(input.as_bytes()).unwrap();
+        engine.finish().unwrap();
+        let written = out.0.lock().unwrap().clone();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn test_csv() {
+        let input = "type,client,tx,amount,currency
+deposit,1,1,5.0,EUR
+deposit,2,2,1.0,
+withdrawal,1,3,9.0,EUR
+deposit,1,6,2.0,
+dispute,1,1,,
+deposit,1,4,-1.0,
+chargeback,1,1,,
+deposit,1,5,1.0,
+";
+        assert_eq!(
+            "client,tx,type,amount,currency,status,code,available,held,total,locked,note
+1,1,deposit,5.0,EUR,applied,,5.0,0.0,5.0,false,
+1,3,withdrawal,9.0,EUR,rejected,account_withdraw,5.0,0.0,5.0,false,
+1,6,deposit,2.0,,applied,,2.0,0.0,2.0,false,
+1,1,dispute,5.0,EUR,applied,,0.0,5.0,5.0,false,dispute_opened
+1,4,deposit,-1.0,,rejected,tx_invalid,2.0,0.0,2.0,false,
+1,1,chargeback,5.0,EUR,applied,,0.0,0.0,0.0,true,charged_back;account_locked
+1,5,deposit,1.0,,rejected,account_locked,2.0,0.0,2.0,true,
+",
+            statement(StatementFormat::Csv, input)
+        );
+    }
+    #[test]
+    fn test_json() {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\n";
+        assert_eq!(
+            r#"{"client":1,"tx":1,"type":"deposit","amount":5.0,"currency":"","status":"applied","code":null,"available":5.0,"held":0.0,"total":5.0,"locked":false,"note":null}"#,
+            statement(StatementFormat::Json, input).trim_end()
+        );
+    }
+    #[test]
+    fn test_fraud_events() {
+        let out = Shared::default();
+        let fraud = Fraud::new(vec![FraudRule::Withdrawals {
+            count: 2,
+            window: 5,
+            action: Action::Hold,
+        }]);
+        let mut engine = Engine::new()
+            .with_fraud(fraud)
+            .with_observer(Statement::new(out.clone(), StatementFormat::Csv));
+        let input = "type,client,tx,amount,currency
+deposit,1,1,5.0,USD
+withdrawal,1,2,1.0,USD
+withdrawal,1,3,1.0,USD
+";
+        engine.process_reader(input.as_bytes()).unwrap();
+        engine.finish().unwrap();
+        let written = out.0.lock().unwrap().clone();
+        assert_eq!(
+            "client,tx,type,amount,currency,status,code,available,held,total,locked,note
+1,1,deposit,5.0,USD,applied,,5.0,0.0,5.0,false,
+1,2,withdrawal,1.0,USD,applied,,4.0,0.0,4.0,false,
+1,3,withdrawal,1.0,USD,applied,,3.0,0.0,3.0,false,
+1,,,,USD,held,velocity_withdrawals,3.0,0.0,3.0,false,account_on_hold
+",
+            String::from_utf8(written).unwrap()
+        );
+    }
+    #[test]
+    fn test_write_error() {
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::StorageFull.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut engine = Engine::new().with_observer(Statement::new(Full, StatementFormat::Json));
+        engine
+            .process_reader("type,client,tx,amount\ndeposit,1,1,5.0\n".as_bytes())
+            .unwrap();
+        assert_eq!(
+            Some(Error::Io(std::io::ErrorKind::StorageFull.into())),
+            engine.finish().err()
+        );
+    }
+}
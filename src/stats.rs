@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{
+    account::Account,
+    currency::Currency,
+    transaction::{Transaction, TransactionKind},
+};
+
+/// Summary of a processing run
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct Stats {
+    pub rows_read: u64,
+    /// Number of applied rows per transaction kind
+    pub applied: BTreeMap<String, u64>,
+    /// Number of rejected rows per reason
+    pub rejected: BTreeMap<String, u64>,
+    /// Number of alerts per triggered fraud rule
+    pub alerts: BTreeMap<String, u64>,
+    pub accounts_created: u64,
+    pub accounts_locked: u64,
+    pub accounts_on_hold: u64,
+    /// Sum of applied deposits per currency
+    pub money_in: BTreeMap<Currency, f64>,
+    /// Sum of applied withdrawals per currency
+    pub money_out: BTreeMap<Currency, f64>,
+    pub elapsed_secs: f64,
+    /// Rows per second
+    pub throughput: f64,
+}
+impl Stats {
+    pub fn record_read(&mut self) {
+        self.rows_read += 1;
+    }
+    pub fn record_applied(&mut self, tx: &Transaction) {
+        *self.applied.entry(tx.kind.to_string()).or_default() += 1;
+        let amount = tx.amount.unwrap_or_default() as f64;
+        match tx.kind {
+            TransactionKind::Deposit => *self.money_in.entry(tx.currency).or_default() += amount,
+            TransactionKind::Withdrawal => {
+                *self.money_out.entry(tx.currency).or_default() += amount
+            }
+            _ => {}
+        }
+    }
+    pub fn record_rejected(&mut self, reason: String) {
+        *self.rejected.entry(reason).or_default() += 1;
+    }
+    pub fn record_alert(&mut self, rule: &str) {
+        *self.alerts.entry(rule.to_string()).or_default() += 1;
+    }
+    pub fn rejected_total(&self) -> u64 {
+        self.rejected.values().sum()
+    }
+    /// Fills in account counters and timing once all rows are processed
+    pub fn finish(&mut self, accounts: &HashMap<u16, Account>, elapsed: Duration) {
+        self.accounts_created = accounts.len() as u64;
+        self.accounts_locked = accounts.values().filter(|a| a.is_locked()).count() as u64;
+        self.accounts_on_hold = accounts.values().filter(|a| a.is_on_hold()).count() as u64;
+        self.elapsed_secs = elapsed.as_secs_f64();
+        self.throughput = if self.elapsed_secs > 0.0 {
+            self.rows_read as f64 / self.elapsed_secs
+        } else {
+            0.0
+        };
+    }
+}
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "rows read: {}", self.rows_read)?;
+        for (kind, n) in &self.applied {
+            writeln!(f, "applied {}: {}", kind, n)?;
+        }
+        for (reason, n) in &self.rejected {
+            writeln!(f, "rejected {}: {}", reason, n)?;
+        }
+        for (rule, n) in &self.alerts {
+            writeln!(f, "alert {}: {}", rule, n)?;
+        }
+        writeln!(
+            f,
+            "accounts created: {}, locked: {}, on hold: {}",
+            self.accounts_created, self.accounts_locked, self.accounts_on_hold
+        )?;
+        let mut currencies: BTreeSet<&Currency> =
+            self.money_in.keys().chain(self.money_out.keys()).collect();
+        if currencies.is_empty() {
+            currencies.insert(&Currency::NONE);
+        }
+        for currency in currencies {
+            // the default currency has no code, so its line is not labelled
+            let label = match currency.is_default() {
+                true => String::new(),
+                false => format!(" {}", currency),
+            };
+            let sum =
+                |money: &BTreeMap<Currency, f64>| money.get(currency).copied().unwrap_or_default();
+            writeln!(
+                f,
+                "money{} in: {}, out: {}",
+                label,
+                sum(&self.money_in),
+                sum(&self.money_out)
+            )?;
+        }
+        write!(
+            f,
+            "elapsed: {:.3}s, throughput: {:.0} rows/s",
+            self.elapsed_secs, self.throughput
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::Stats;
+    use crate::{
+        account::Account,
+        currency::Currency,
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        let deposit = Transaction {
+            kind: TransactionKind::Deposit,
+            client_id: 1,
+            tx: 1,
+            amount: Some(2.0),
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        };
+        let withdraw = Transaction {
+            kind: TransactionKind::Withdrawal,
+            tx: 2,
+            amount: Some(0.5),
+            ..deposit.clone()
+        };
+        stats.record_read();
+        stats.record_applied(&deposit);
+        stats.record_read();
+        stats.record_applied(&withdraw);
+        stats.record_read();
+        stats.record_rejected("parse".to_string());
+        let mut accounts = HashMap::new();
+        accounts.insert(1, Account::new(1));
+        stats.finish(&accounts, Duration::from_secs(2));
+
+        assert_eq!(3, stats.rows_read);
+        assert_eq!(Some(&1), stats.applied.get("deposit"));
+        assert_eq!(Some(&1), stats.applied.get("withdrawal"));
+        assert_eq!(1, stats.rejected_total());
+        assert_eq!(1, stats.accounts_created);
+        assert_eq!(0, stats.accounts_locked);
+        assert_eq!(Some(&2.0), stats.money_in.get(&Currency::NONE));
+        assert_eq!(Some(&0.5), stats.money_out.get(&Currency::NONE));
+        assert_eq!(1.5, stats.throughput);
+
+        let usd: Currency = "USD".parse().unwrap();
+        stats.record_applied(&Transaction {
+            tx: 3,
+            amount: Some(7.0),
+            currency: usd,
+            ..deposit.clone()
+        });
+        assert_eq!(Some(&2.0), stats.money_in.get(&Currency::NONE));
+        assert_eq!(Some(&7.0), stats.money_in.get(&usd));
+        assert_eq!(None, stats.money_out.get(&usd));
+        let summary = stats.to_string();
+        assert!(summary.contains("\nmoney in: 2, out: 0.5\n"), "{}", summary);
+        assert!(
+            summary.contains("\nmoney USD in: 7, out: 0\n"),
+            "{}",
+            summary
+        );
+        assert!(serde_json::to_string(&stats)
+            .unwrap()
+            .contains(r#""money_in":{"":2.0,"USD":7.0}"#));
+    }
+}
@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Write;
+
+use crate::error::Error;
+
+/// Limits above which deposits and withdrawals are reported
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Thresholds {
+    /// A single deposit or withdrawal above this amount is reported
+    pub amount: Option<f32>,
+    /// A client is reported when its deposits and withdrawals in one currency within `window` rows sum above this amount
+    pub aggregate: Option<f32>,
+    /// Number of processed rows the aggregate is counted over, including the current one
+    pub window: u64,
+}
+
+/// Deposits and withdrawals that crossed a threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct LargeRow {
+    /// Input line of the row that crossed the threshold
+    pub line: u64,
+    /// `amount` for a single large transaction, `aggregate` for the movement of a client
+    pub rule: &'static str,
+    pub client: u16,
+    /// Ids and amounts of the transactions, oldest first
+    pub txs: Vec<(u32, f32)>,
+    pub aggregate: f32,
+}
+
+/// Writes `line,rule,client,txs,amounts,aggregate` of every crossed threshold,
+/// transaction ids and amounts are separated by `;`
+pub struct LargeReport {
+    thresholds: Thresholds,
+    wtr: RefCell<csv::Writer<Box<dyn Write + Send>>>,
+    /// Deposits and withdrawals within the aggregate window as `(seq, tx id)`, oldest first
+    recent: RefCell<VecDeque<(u64, u32)>>,
+}
+impl LargeReport {
+    pub fn new(thresholds: Thresholds, w: impl Write + Send + 'static) -> Result<Self, Error> {
+        let mut wtr = csv::Writer::from_writer(Box::new(w) as Box<dyn Write + Send>);
+        wtr.write_record(["line", "rule", "client", "txs", "amounts", "aggregate"])?;
+        Ok(LargeReport {
+            thresholds,
+            wtr: RefCell::new(wtr),
+            recent: RefCell::default(),
+        })
+    }
+    pub fn thresholds(&self) -> &Thresholds {
+        &self.thresholds
+    }
+    /// Adds a deposit or withdrawal applied at the `Db` row `seq` and drops the ones out of the window
+    pub fn push(&self, seq: u64, tx_id: u32) {
+        let mut recent = self.recent.borrow_mut();
+        recent.push_back((seq, tx_id));
+        while recent
+            .front()
+            .is_some_and(|(first, _)| first + self.thresholds.window <= seq)
+        {
+            recent.pop_front();
+        }
+    }
+    /// Ids of the deposits and withdrawals within the aggregate window, oldest first
+    pub fn recent(&self) -> Vec<u32> {
+        self.recent
+            .borrow()
+            .iter()
+            .map(|(_, tx_id)| *tx_id)
+            .collect()
+    }
+    pub fn write(&self, row: &LargeRow) -> Result<(), Error> {
+        let join =
+            |f: &dyn Fn(&(u32, f32)) -> String| row.txs.iter().map(f).collect::<Vec<_>>().join(";");
+        self.wtr.borrow_mut().write_record([
+            row.line.to_string(),
+            row.rule.to_string(),
+            row.client.to_string(),
+            join(&|(tx, _)| tx.to_string()),
+            join(&|(_, amount)| amount.to_string()),
+            row.aggregate.to_string(),
+        ])?;
+        Ok(())
+    }
+    pub fn flush(&self) -> Result<(), Error> {
+        self.wtr.borrow_mut().flush()?;
+        Ok(())
+    }
+}
+impl std::fmt::Debug for LargeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LargeReport")
+            .field("thresholds", &self.thresholds)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use super::{LargeReport, Thresholds};
+    use crate::{
+        currency::Currency,
+        db::Db,
+        transaction::{Transaction, TransactionKind, TransactionState},
+    };
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn tx(kind: TransactionKind, client_id: u16, tx: u32, amount: Option<f32>) -> Transaction {
+        Transaction {
+            kind,
+            client_id,
+            tx,
+            amount,
+            currency: Currency::NONE,
+            state: TransactionState::Processing,
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        use TransactionKind::*;
+        let out = Shared::default();
+        let thresholds = Thresholds {
+            amount: Some(100.0),
+            aggregate: Some(50.0),
+            window: 3,
+        };
+        let db =
+            Db::default().with_large_report(LargeReport::new(thresholds, out.clone()).unwrap());
+        db.clean();
+        for t in [
+            tx(Deposit, 1, 1, Some(20.0)),
+            tx(Deposit, 2, 2, Some(40.0)),
+            tx(Withdrawal, 1, 3, Some(15.0)),
+            tx(Deposit, 1, 4, Some(20.0)),
+            tx(Deposit, 1, 5, Some(20.0)),
+            tx(Dispute, 1, 5, None),
+            tx(Deposit, 2, 6, Some(150.0)),
+            tx(Withdrawal, 2, 7, Some(500.0)),
+        ] {
+            let _ = db.process(t);
+        }
+        db.flush().unwrap();
+        assert_eq!(vec![(5, 20.0)], db.recent_transactions(1, Currency::NONE));
+        db.clean();
+        assert_eq!(
+            "line,rule,client,txs,amounts,aggregate\n\
+             5,aggregate,1,3;4;5,15;20;20,55\n\
+             7,amount,2,6,150,150\n\
+             7,aggregate,2,6,150,150\n",
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap()
+        );
+    }
+}
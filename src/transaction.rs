@@ -2,7 +2,7 @@ use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::{currency::Currency, error::Error};
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct Transaction {
@@ -12,6 +12,9 @@ pub struct Transaction {
     pub client_id: u16,
     pub tx: u32,
     pub amount: Option<f32>,
+    /// Optional `currency` column, disputes, resolves and chargebacks act on the currency of the referenced transaction
+    #[serde(default, skip_serializing_if = "Currency::is_default")]
+    pub currency: Currency,
     #[serde(skip, default = "TransactionState::default")]
     pub state: TransactionState,
 }
@@ -19,7 +22,7 @@ impl Transaction {
     pub fn is_valid(&self) -> bool {
         use TransactionKind::*;
         match self.kind {
-            Withdrawal | Deposit => self.amount.filter(|x| x >= &0.0).is_some(),
+            Withdrawal | Deposit => self.amount.filter(|x| x.is_finite() && *x > 0.0).is_some(),
             _ => self.amount.is_none(),
         }
     }
@@ -54,7 +57,7 @@ impl Display for Transaction {
         )
     }
 }
-#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionKind {
     Withdrawal,
@@ -68,8 +71,19 @@ impl TransactionKind {
         *self == Self::Deposit
     }
 }
+impl Display for TransactionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Withdrawal => write!(f, "withdrawal"),
+            Self::Deposit => write!(f, "deposit"),
+            Self::Dispute => write!(f, "dispute"),
+            Self::Resolve => write!(f, "resolve"),
+            Self::Chargeback => write!(f, "chargeback"),
+        }
+    }
+}
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy, Default)]
 pub enum TransactionState {
     #[default]
     Processing,
@@ -82,10 +96,16 @@ pub enum TransactionState {
 #[derive(Debug, Clone, PartialEq)]
 pub enum TransactionError {
     NotFound,
+    ClientMismatch,
+    Duplicate,
+    Expired,
+    Invalid,
     UnExpectedAmount,
     Dispute,
     Resolve,
     Chargeback,
+    /// The transaction table cannot store another currency
+    CurrencyLimit,
 }
 
 impl Display for TransactionError {
@@ -95,10 +115,33 @@ impl Display for TransactionError {
             Self::Resolve => write!(f, "Transaction has incorrect state and cannot be resolved"),
             Self::Chargeback => write!(f, "Transaction has incorrent state and cannot be charged back"),
             Self::NotFound => write!(f, "Transaction not found"),
+            Self::ClientMismatch => write!(f, "Transaction belongs to another client"),
+            Self::Duplicate => write!(f, "Transaction id is already used"),
+            Self::Expired => write!(f, "Transaction is out of the dispute window"),
+            Self::Invalid => write!(f, "Transaction has zero, negative, infinite or unexpected amount"),
+            Self::CurrencyLimit => write!(f, "Too many currencies to store the transaction"),
             Self::UnExpectedAmount => write!(f, "Transaction has unexpected amount. Either it is deposit/withdrawal without amount or disput/resolve/chargeback with amount."),
         }
     }
 }
+impl std::error::Error for TransactionError {}
+impl TransactionError {
+    /// Stable code of the error used in reports and summaries
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound => "tx_not_found",
+            Self::ClientMismatch => "tx_client_mismatch",
+            Self::Duplicate => "tx_duplicate",
+            Self::Expired => "tx_expired",
+            Self::Invalid => "tx_invalid",
+            Self::UnExpectedAmount => "tx_unexpected_amount",
+            Self::CurrencyLimit => "tx_currency_limit",
+            Self::Dispute => "tx_dispute",
+            Self::Resolve => "tx_resolve",
+            Self::Chargeback => "tx_chargeback",
+        }
+    }
+}
 impl From<TransactionError> for Error {
     fn from(value: TransactionError) -> Self {
         Self::Transaction(value)
@@ -0,0 +1,44 @@
+//! Strategies shared by the property tests
+use jones_project::currency::Currency;
+use jones_project::transaction::{Transaction, TransactionKind, TransactionState};
+use proptest::prelude::*;
+
+pub fn kind() -> impl Strategy<Value = TransactionKind> {
+    use TransactionKind::*;
+    prop_oneof![
+        4 => Just(Deposit),
+        2 => Just(Withdrawal),
+        2 => Just(Dispute),
+        1 => Just(Resolve),
+        1 => Just(Chargeback),
+    ]
+}
+
+/// Mostly the default currency, so disputes of other currencies meet funds in the default one
+pub fn currency() -> impl Strategy<Value = Currency> {
+    prop_oneof![
+        2 => Just(Currency::NONE),
+        1 => Just("USD".parse().unwrap()),
+        1 => Just("EUR".parse().unwrap()),
+    ]
+}
+
+/// Ids are taken from a small range, so disputes often refer to existing transactions.
+/// Deposits and withdrawals are spread over a few currencies.
+/// Amounts are multiples of 0.25, so sums of `f32` are exact and can be compared with `==`
+pub fn transaction(clients: u16, tx_ids: u32) -> impl Strategy<Value = Transaction> {
+    (kind(), 1..=clients, 1..=tx_ids, 0..=400u32, currency()).prop_map(
+        |(kind, client_id, tx, quarters, currency)| {
+            let moves = matches!(kind, TransactionKind::Deposit | TransactionKind::Withdrawal);
+            Transaction {
+                kind,
+                client_id,
+                tx,
+                amount: moves.then_some(quarters as f32 / 4.0),
+                // dispute, resolve and chargeback rows refer to the currency of the transaction
+                currency: if moves { currency } else { Currency::NONE },
+                state: TransactionState::Processing,
+            }
+        },
+    )
+}
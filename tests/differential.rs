@@ -0,0 +1,83 @@
+//! Differential tests: random and generated streams run through both `Db` and the
+//! reference `Model`, a failure reports the first diverging row and account.
+use clap::ValueEnum;
+use jones_project::{
+    db::Db,
+    generator::{Config, Generator, Scenario},
+    model,
+    transaction::{Transaction, TransactionState},
+};
+use proptest::{prelude::*, test_runner::FileFailurePersistence};
+
+mod common;
+
+/// Unlike `common::transaction` any kind can carry an amount, including a zero, negative or
+/// non-finite one, and a currency
+fn any_transaction() -> impl Strategy<Value = Transaction> {
+    (
+        common::kind(),
+        1..=4u16,
+        1..=24u32,
+        prop::option::of(prop_oneof![
+            20 => (-40..=400i32).prop_map(|quarters| quarters as f32 / 4.0),
+            1 => Just(f32::INFINITY),
+            1 => Just(f32::NEG_INFINITY),
+            1 => Just(f32::NAN),
+        ]),
+        common::currency(),
+    )
+        .prop_map(|(kind, client_id, tx, amount, currency)| Transaction {
+            kind,
+            client_id,
+            tx,
+            amount,
+            currency,
+            state: TransactionState::Processing,
+        })
+}
+
+fn check(txs: impl IntoIterator<Item = Transaction>) -> Result<(), String> {
+    let db = Db::default();
+    db.clean();
+    model::diff(&db, txs).map(|_| ()).map_err(|d| d.to_string())
+}
+
+#[test]
+fn test_generated() {
+    for (i, scenario) in Scenario::value_variants().iter().enumerate() {
+        for malformed_rate in [0.0, 0.1] {
+            let config = Config {
+                malformed_rate,
+                ..Config::new(20, 5000)
+                    .with_seed(i as u64)
+                    .with_scenario(*scenario)
+            };
+            let txs = Generator::new(config).filter_map(|row| row.transaction().cloned());
+            if let Err(divergence) = check(txs) {
+                panic!(
+                    "{:?} malformed {}: {}",
+                    scenario, malformed_rate, divergence
+                );
+            }
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        failure_persistence: Some(Box::new(FileFailurePersistence::Direct(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/proptest-regressions/differential.txt"
+        )))),
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn test_random(txs in prop::collection::vec(common::transaction(4, 24), 1..100)) {
+        check(txs).map_err(TestCaseError::fail)?;
+    }
+    #[test]
+    fn test_random_malformed(txs in prop::collection::vec(any_transaction(), 1..100)) {
+        check(txs).map_err(TestCaseError::fail)?;
+    }
+}
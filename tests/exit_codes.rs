@@ -0,0 +1,70 @@
+//! Exit codes of the main binary when an output file cannot be written or an input file is invalid
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn main(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_main"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_spill_failure() {
+    let input = fixture("spill").join("input.csv");
+    let output = main(&[
+        input.to_str().unwrap(),
+        "--dispute-window-count",
+        "2",
+        "--spill",
+        "/nonexistent/dir/spill.bin",
+    ]);
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("I/O error"), "{}", stderr);
+}
+#[test]
+fn test_statement_failure() {
+    // every write to the device fails with "no space left"
+    if !Path::new("/dev/full").exists() {
+        return;
+    }
+    let input = fixture("statement").join("input.csv");
+    let output = main(&[input.to_str().unwrap(), "--statement", "/dev/full"]);
+    assert_eq!(Some(1), output.status.code());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("I/O error"), "{}", stderr);
+}
+#[test]
+fn test_invalid_state() {
+    let input = fixture("load_state").join("input.csv");
+    let dir = std::env::temp_dir().join(format!("jones-state-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, content) in [
+        ("corrupt.json", "{\"version\": 2, \"accounts\": ["),
+        ("version.json", "{\"version\": 99}"),
+    ] {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        let output = main(&[
+            input.to_str().unwrap(),
+            "--load-state",
+            path.to_str().unwrap(),
+        ]);
+        assert_eq!(Some(2), output.status.code(), "{}", name);
+    }
+    let missing = dir.join("missing.json");
+    let output = main(&[
+        input.to_str().unwrap(),
+        "--load-state",
+        missing.to_str().unwrap(),
+    ]);
+    assert_eq!(Some(1), output.status.code());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,150 @@
+//! Feeds generated files to the main binary and compares its output with the
+//! expected accounts computed by the generator's independent model.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const NUM_CLIENTS: &str = "50";
+const NUM_LINES: &str = "20000";
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jones-generated-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn generate(dir: &Path, scenario: &str, seed: u64, extra: &[&str]) -> (PathBuf, PathBuf) {
+    let input = dir.join("transactions.csv");
+    let expected = dir.join("expected.csv");
+    let output = Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args([NUM_CLIENTS, NUM_LINES, "--scenario", scenario])
+        .arg("--seed")
+        .arg(seed.to_string())
+        .arg("--expect")
+        .arg(&expected)
+        .args(extra)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "generator failed: {:?}", output);
+    std::fs::write(&input, output.stdout).unwrap();
+    (input, expected)
+}
+
+/// Account rows are printed in no particular order
+fn sorted_rows(data: &str) -> Vec<String> {
+    let mut lines = data.lines();
+    assert_eq!(Some("client,available,held,total,locked"), lines.next());
+    let mut rows: Vec<String> = lines.map(|l| l.to_string()).collect();
+    rows.sort_by_key(|l| l.split(',').next().unwrap().parse::<u16>().unwrap());
+    rows
+}
+
+/// Reads `line` and `code` columns of a CSV file into a map
+fn codes(path: &Path, code_column: usize) -> HashMap<u64, String> {
+    let mut rdr = csv::Reader::from_path(path).unwrap();
+    rdr.records()
+        .map(|r| {
+            let r = r.unwrap();
+            (r[0].parse().unwrap(), r[code_column].to_string())
+        })
+        .collect()
+}
+
+fn check(scenario: &str, seed: u64) {
+    check_with(scenario, seed, None, &[])
+}
+
+/// With `malformed_rate` every injected row is checked against the rejects report of the main binary
+fn check_with(scenario: &str, seed: u64, malformed_rate: Option<&str>, args: &[&str]) {
+    let dir = work_dir(&format!("{}-{}", scenario, seed));
+    let tags = dir.join("tags.csv");
+    let mut extra = match malformed_rate {
+        Some(rate) => vec!["--malformed-rate", rate, "--tags", tags.to_str().unwrap()],
+        None => vec![],
+    };
+    extra.extend_from_slice(args);
+    let (input, expected) = generate(&dir, scenario, seed, &extra);
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .arg(&input)
+        .arg("--rejects")
+        .arg(dir.join("rejects.csv"))
+        .output()
+        .unwrap();
+    assert!(
+        matches!(output.status.code(), Some(0) | Some(3)),
+        "main failed: {:?}",
+        output
+    );
+    let actual = sorted_rows(&String::from_utf8(output.stdout).unwrap());
+    let expected = sorted_rows(&std::fs::read_to_string(&expected).unwrap());
+    for (a, e) in actual.iter().zip(expected.iter()) {
+        assert_eq!(e, a, "scenario {} seed {}", scenario, seed);
+    }
+    assert_eq!(
+        expected.len(),
+        actual.len(),
+        "scenario {} seed {}",
+        scenario,
+        seed
+    );
+    if malformed_rate.is_some() {
+        let rejects = codes(&dir.join("rejects.csv"), 3);
+        let tags = codes(&tags, 2);
+        assert!(!tags.is_empty());
+        for (line, expected) in tags {
+            let actual = rejects.get(&line).map(|c| c.as_str()).unwrap_or_default();
+            assert_eq!(expected, actual, "line {} seed {}", line, seed);
+        }
+    }
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn test_default() {
+    check("default", 1);
+    check("default", 2);
+}
+#[test]
+fn test_heavy_disputes() {
+    check("heavy-disputes", 3);
+}
+#[test]
+fn test_chargeback_storm() {
+    check("chargeback-storm", 4);
+}
+#[test]
+fn test_overdraft() {
+    check("overdraft", 5);
+}
+#[test]
+fn test_locked_traffic() {
+    check("locked-traffic", 6);
+}
+#[test]
+fn test_malformed() {
+    check_with("default", 7, Some("0.05"), &[]);
+    check_with("heavy-disputes", 8, Some("0.2"), &[]);
+}
+#[test]
+fn test_threads() {
+    check_with("default", 9, Some("0.05"), &["--threads", "3"]);
+}
+/// The same file in JSONL gives the same accounts
+#[test]
+fn test_jsonl() {
+    let dir = work_dir("jsonl");
+    let (csv, _) = generate(&dir, "default", 10, &["--format", "jsonl"]);
+    let jsonl = dir.join("transactions.jsonl");
+    std::fs::rename(&csv, &jsonl).unwrap();
+    let (csv, _) = generate(&dir, "default", 10, &[]);
+    let run = |input: &Path, format: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_main"))
+            .arg(input)
+            .args(["--format", format])
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(run(&csv, "csv"), run(&jsonl, "jsonl"));
+    let _ = std::fs::remove_dir_all(dir);
+}
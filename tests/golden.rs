@@ -0,0 +1,156 @@
+//! Runs the main binary on every `tests/fixtures/<name>/input.csv` and compares
+//! its output with `expected.csv` and its rejects report with `rejects.csv`.
+//!
+//! Extra arguments of a fixture are read from its `args` file, one or more per line,
+//! `{dir}` is replaced with a temporary directory of the run and `{fixture}` with the fixture directory.
+//! Other CSV files written to `{dir}` are compared with the fixture files of the same name.
+//! Run with `UPDATE_GOLDEN=1` to write the current output as the expectations.
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jones-golden-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Account rows are printed in no particular order
+fn sort_accounts(data: &str) -> String {
+    let mut lines = data.lines();
+    let header = lines.next().unwrap_or_default();
+    let mut rows: Vec<&str> = lines.collect();
+    rows.sort_by_key(|l| l.split(',').next().unwrap().parse::<u16>().unwrap());
+    let mut out = format!("{}\n", header);
+    for row in rows {
+        out.push_str(row);
+        out.push('\n');
+    }
+    out
+}
+
+/// Names and contents of the CSV files written to `dir` besides the rejects report
+fn reports(dir: &Path) -> Vec<(String, String)> {
+    let mut reports: Vec<(String, String)> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "csv"))
+        .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+        .filter(|name| name != "rejects.csv")
+        .map(|name| {
+            let data = std::fs::read_to_string(dir.join(&name)).unwrap();
+            (name, data)
+        })
+        .collect();
+    reports.sort();
+    reports
+}
+
+/// Returns a description of the mismatch, or `None` if the output matches the expectations
+fn run(name: &str, input: &Path) -> Option<String> {
+    let fixture = fixtures_dir().join(name);
+    let dir = work_dir(name);
+    let rejects = dir.join("rejects.csv");
+    let args = std::fs::read_to_string(fixture.join("args")).unwrap_or_default();
+    let args = args
+        .replace("{dir}", dir.to_str().unwrap())
+        .replace("{fixture}", fixture.to_str().unwrap());
+    let output = Command::new(env!("CARGO_BIN_EXE_main"))
+        .arg(input)
+        .args(args.split_whitespace())
+        .arg("--rejects")
+        .arg(&rejects)
+        .output()
+        .unwrap();
+    let accounts = sort_accounts(&String::from_utf8(output.stdout).unwrap());
+    let rejects = std::fs::read_to_string(&rejects).unwrap();
+    let reports = reports(&dir);
+    let _ = std::fs::remove_dir_all(dir);
+    let status = output.status.code();
+    let expected_status = if rejects.lines().count() > 1 { 3 } else { 0 };
+    if status != Some(expected_status) {
+        return Some(format!("{}: exit code {:?}", name, status));
+    }
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(fixture.join("expected.csv"), accounts).unwrap();
+        std::fs::write(fixture.join("rejects.csv"), rejects).unwrap();
+        for (name, data) in reports {
+            std::fs::write(fixture.join(name), data).unwrap();
+        }
+        return None;
+    }
+    for (report, data) in reports {
+        let expected = std::fs::read_to_string(fixture.join(&report)).unwrap_or_default();
+        if expected != data {
+            return Some(format!(
+                "{}: expected {}\n{}got\n{}",
+                name, report, expected, data
+            ));
+        }
+    }
+    let expected = std::fs::read_to_string(fixture.join("expected.csv")).unwrap_or_default();
+    let expected_rejects = std::fs::read_to_string(fixture.join("rejects.csv")).unwrap_or_default();
+    if expected != accounts {
+        Some(format!(
+            "{}: expected accounts\n{}got\n{}",
+            name, expected, accounts
+        ))
+    } else if expected_rejects != rejects {
+        Some(format!(
+            "{}: expected rejects\n{}got\n{}",
+            name, expected_rejects, rejects
+        ))
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_fixtures() {
+    let mut names: Vec<String> = std::fs::read_dir(fixtures_dir())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.join("input.csv").exists())
+        .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert!(!names.is_empty());
+    let failures: Vec<String> = names
+        .iter()
+        .filter_map(|name| run(name, &fixtures_dir().join(name).join("input.csv")))
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+/// The example file of the repository, its expectations are in `tests/fixtures/transactions`
+#[test]
+fn test_transactions_csv() {
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("transactions.csv");
+    if let Some(failure) = run("transactions", &input) {
+        panic!("{}", failure);
+    }
+}
+/// `-` reads the same example from the standard input
+#[test]
+fn test_stdin() {
+    let input =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("transactions.csv")).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_main"))
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let expected =
+        std::fs::read_to_string(fixtures_dir().join("transactions/expected.csv")).unwrap();
+    assert_eq!(
+        expected,
+        sort_accounts(&String::from_utf8(output.stdout).unwrap())
+    );
+}
@@ -0,0 +1,135 @@
+//! Property tests of the money invariants of `Db::process` on random sequences of transactions.
+//!
+//! Failing cases are shrunk and saved to `tests/proptest-regressions/invariants.txt`,
+//! saved cases are replayed first on every run.
+use std::collections::{BTreeMap, BTreeSet};
+
+use jones_project::{
+    currency::Currency,
+    db::Db,
+    transaction::{Transaction, TransactionKind, TransactionState},
+};
+use proptest::{prelude::*, test_runner::FileFailurePersistence};
+
+mod common;
+
+const CLIENTS: u16 = 4;
+const TX_IDS: u32 = 24;
+
+/// Money moved by applied rows.
+/// Disputing a withdrawal credits its amount back to held funds, so from then on it is not
+/// counted as withdrawn; a resolve releases it to the client and a chargeback takes it out
+/// of the system again as a chargeback
+#[derive(Default)]
+struct Ledger {
+    deposits: f32,
+    /// Applied withdrawals that are not disputed
+    withdrawals: f32,
+    chargebacks: f32,
+    /// Ids of applied deposits and withdrawals
+    txs: BTreeSet<u32>,
+}
+impl Ledger {
+    fn record(&mut self, db: &Db, tx: &Transaction) {
+        let stored = || {
+            db.transaction(tx.tx)
+                .expect("applied row refers to a stored transaction")
+        };
+        match tx.kind {
+            TransactionKind::Deposit => self.deposits += tx.amount.unwrap(),
+            TransactionKind::Withdrawal => self.withdrawals += tx.amount.unwrap(),
+            TransactionKind::Dispute => {
+                let stored = stored();
+                if stored.kind == TransactionKind::Withdrawal {
+                    self.withdrawals -= stored.amount.unwrap();
+                }
+            }
+            TransactionKind::Resolve => {}
+            TransactionKind::Chargeback => self.chargebacks += stored().amount.unwrap(),
+        }
+        if tx.amount.is_some() {
+            self.txs.insert(tx.tx);
+        }
+    }
+    fn money(&self) -> f32 {
+        self.deposits - self.withdrawals - self.chargebacks
+    }
+}
+
+fn check(db: &Db, ledger: &Ledger) -> Result<(), TestCaseError> {
+    let mut disputed = BTreeMap::<(u16, Currency), f32>::new();
+    for tx in ledger.txs.iter().filter_map(|id| db.transaction(*id)) {
+        if tx.state == TransactionState::Dispute {
+            *disputed.entry((tx.client_id, tx.currency)).or_default() += tx.amount.unwrap();
+        }
+    }
+    let mut money = 0.0;
+    for client_id in 1..=CLIENTS {
+        let Some(acc) = db.account(client_id) else {
+            continue;
+        };
+        for (currency, funds) in acc.currencies() {
+            prop_assert_eq!(funds.total, funds.available + funds.held, "{:?}", acc);
+            let held = disputed
+                .get(&(client_id, currency))
+                .copied()
+                .unwrap_or_default();
+            prop_assert_eq!(held, funds.held, "{:?}", acc);
+            money += funds.total;
+        }
+    }
+    prop_assert_eq!(ledger.money(), money);
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig {
+        cases: 512,
+        failure_persistence: Some(Box::new(FileFailurePersistence::Direct(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/proptest-regressions/invariants.txt"
+        )))),
+        ..ProptestConfig::default()
+    })]
+
+    #[test]
+    fn test_money_invariants(txs in prop::collection::vec(common::transaction(CLIENTS, TX_IDS), 1..80)) {
+        let db = Db::default();
+        db.clean();
+        let mut ledger = Ledger::default();
+        for tx in txs {
+            let before = db.account(tx.client_id);
+            let result = db.process(tx.clone());
+            if let Some(before) = before.filter(|acc| acc.is_locked()) {
+                prop_assert!(result.is_err(), "locked account accepted {}", tx);
+                let after = db.account(tx.client_id).unwrap();
+                prop_assert_eq!(
+                    (before.currencies().collect::<Vec<_>>(), after.is_locked()),
+                    (after.currencies().collect::<Vec<_>>(), true)
+                );
+            }
+            if result.is_ok() {
+                ledger.record(&db, &tx);
+            }
+            check(&db, &ledger)?;
+        }
+    }
+    /// Point-in-time queries return the account as it was after every row, fraud holds and locks included
+    #[test]
+    fn test_account_at(txs in prop::collection::vec(common::transaction(CLIENTS, TX_IDS), 1..80), interval in 1..10u64) {
+        let db = Db::default().with_snapshots(interval);
+        let mut history = vec![];
+        for (i, tx) in txs.into_iter().enumerate() {
+            let _ = db.process(tx);
+            if i % 7 == 6 {
+                db.hold_account(1, "test");
+            }
+            history.push((1..=CLIENTS).map(|c| db.account(c)).collect::<Vec<_>>());
+        }
+        for (i, accounts) in history.iter().enumerate() {
+            for (client_id, acc) in (1..=CLIENTS).zip(accounts) {
+                prop_assert_eq!(acc, &db.account_at(client_id, i as u64 + 1), "client {} row {}", client_id, i + 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Runs the reconcile binary on the expectations of the golden fixtures
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn reconcile(args: &[&Path]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_reconcile"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_matching_files() {
+    let expected = fixture("transactions").join("expected.csv");
+    let output = reconcile(&[&expected, &expected]);
+    assert_eq!(Some(0), output.status.code());
+    let input = Path::new(env!("CARGO_MANIFEST_DIR")).join("transactions.csv");
+    let output = reconcile(&[&expected, Path::new("--input"), &input]);
+    assert_eq!(Some(0), output.status.code());
+}
+#[test]
+fn test_mismatch() {
+    let output = reconcile(&[
+        &fixture("transactions").join("expected.csv"),
+        &fixture("insufficient_funds").join("expected.csv"),
+    ]);
+    assert_eq!(Some(4), output.status.code());
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        report.starts_with("client,currency,status,fields,"),
+        "{}",
+        report
+    );
+    assert!(report.lines().count() > 1, "{}", report);
+    let summary = String::from_utf8(output.stderr).unwrap();
+    assert!(summary.starts_with("matched: "), "{}", summary);
+}
+#[test]
+fn test_missing_file() {
+    let output = reconcile(&[&fixture("none.csv"), &fixture("none.csv")]);
+    assert_eq!(Some(1), output.status.code());
+}
+#[test]
+fn test_currencies() {
+    let expected = fixture("currencies").join("expected.csv");
+    let output = reconcile(&[&expected, &expected]);
+    assert_eq!(Some(0), output.status.code());
+    let output = reconcile(&[&expected, &fixture("transactions").join("expected.csv")]);
+    assert_eq!(Some(4), output.status.code());
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.contains("\n1,EUR,missing,"), "{}", report);
+    assert!(report.contains("\n2,EUR,missing,"), "{}", report);
+}